@@ -0,0 +1,132 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::engine::{InputTransaction, PaymentEngine, TransactionType};
+use rs_accountant::generator;
+use rust_decimal_macros::dec;
+
+const NUM_CLIENTS: u16 = 1000;
+const NUM_TRANSACTIONS: u32 = 100_000;
+const SEED: u64 = 42;
+
+fn transaction(transaction_type: TransactionType, client_id: u16, tx_id: u32, with_amount: bool) -> InputTransaction {
+    InputTransaction {
+        transaction_type,
+        client_id,
+        tx_id,
+        amount: with_amount.then(|| dec!(10.5)),
+        currency: None,
+        reference: None,
+        seq: None,
+    }
+}
+
+fn deposits() -> Vec<InputTransaction> {
+    (1..=NUM_TRANSACTIONS)
+        .map(|tx_id| transaction(TransactionType::Deposit, (tx_id % u32::from(NUM_CLIENTS)) as u16, tx_id, true))
+        .collect()
+}
+
+/// Every deposit is disputed, then alternately resolved or charged back.
+fn dispute_workload() -> Vec<InputTransaction> {
+    let deposits = deposits();
+    let mut transactions = deposits.clone();
+    for deposit in &deposits {
+        transactions.push(transaction(TransactionType::Dispute, deposit.client_id, deposit.tx_id, false));
+    }
+    for deposit in &deposits {
+        let closing = if deposit.tx_id % 2 == 0 { TransactionType::Resolve } else { TransactionType::Chargeback };
+        transactions.push(transaction(closing, deposit.client_id, deposit.tx_id, false));
+    }
+    transactions
+}
+
+fn bench_deposit(c: &mut Criterion) {
+    let transactions = deposits();
+    let mut group = c.benchmark_group("handle_deposit");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("deposits", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| {
+                let mut engine = PaymentEngine::new();
+                for tx in transactions {
+                    let _ = black_box(engine.handle_deposit(tx));
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_withdrawal(c: &mut Criterion) {
+    let funding = deposits();
+    let withdrawals: Vec<_> = funding
+        .iter()
+        .map(|deposit| transaction(TransactionType::Withdrawal, deposit.client_id, deposit.tx_id + NUM_TRANSACTIONS, true))
+        .collect();
+    let mut group = c.benchmark_group("handle_withdrawal");
+    group.throughput(Throughput::Elements(withdrawals.len() as u64));
+    group.bench_function("withdrawals", |b| {
+        b.iter_batched(
+            || {
+                let mut engine = PaymentEngine::new();
+                for tx in funding.iter().cloned() {
+                    engine.handle_deposit(tx).unwrap();
+                }
+                (engine, withdrawals.clone())
+            },
+            |(mut engine, withdrawals)| {
+                for tx in withdrawals {
+                    let _ = black_box(engine.handle_withdrawal(tx));
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_disputes(c: &mut Criterion) {
+    let transactions = dispute_workload();
+    let mut group = c.benchmark_group("apply");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("dispute_workload", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| {
+                let mut engine = PaymentEngine::new();
+                for tx in transactions {
+                    let _ = black_box(engine.apply(tx));
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_process_transactions(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let (transactions, _) = generator::generate(&mut rng, NUM_CLIENTS, NUM_TRANSACTIONS);
+    let mut csv = Vec::new();
+    generator::write_transactions(&transactions, &mut csv).unwrap();
+
+    let mut group = c.benchmark_group("process_transactions");
+    group.throughput(Throughput::Elements(u64::from(NUM_TRANSACTIONS)));
+    group.bench_function("csv_100k", |b| {
+        b.iter(|| {
+            let mut engine = PaymentEngine::new();
+            engine.process_transactions(black_box(csv.as_slice())).unwrap();
+            engine
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_deposit, bench_withdrawal, bench_disputes, bench_process_transactions);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// A monetary amount the engine can keep balances in.
+///
+/// The engine itself only adds, subtracts, negates and compares amounts, so a backend such as
+/// a fixed-point integer can be swapped in for `Decimal`. Fees, interest and output are
+/// calculated on `Decimal`, converting through `to_decimal` and `from_decimal`.
+pub trait Amount:
+    Copy
+    + Debug
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Sum
+    + DeserializeOwned
+{
+    /// The zero amount.
+    const ZERO: Self;
+
+    /// Returns the absolute value of the amount.
+    fn abs(self) -> Self;
+
+    /// Adds two amounts, returning `None` on overflow.
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// Subtracts an amount, returning `None` on overflow.
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    /// Converts the amount to a `Decimal`.
+    fn to_decimal(self) -> Decimal;
+
+    /// Converts a `Decimal`, such as a calculated fee, to the amount type. Implementations with
+    /// a fixed precision round the value to it.
+    fn from_decimal(value: Decimal) -> Self;
+}
+
+impl Amount for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+
+    fn abs(self) -> Self {
+        Decimal::abs(&self)
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+
+    fn from_decimal(value: Decimal) -> Self {
+        value
+    }
+}
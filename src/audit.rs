@@ -0,0 +1,45 @@
+use crate::engine::{ClientId, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+
+/// A record of a single successfully applied transaction and its effect on the account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The ID of the transaction.
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    /// The ID of the client the transaction was applied to.
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The type of the transaction.
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    /// The amount of the transaction, if it had one.
+    pub amount: Option<Decimal>,
+    /// The account's available funds before the transaction.
+    pub available_before: Decimal,
+    /// The account's held funds before the transaction.
+    pub held_before: Decimal,
+    /// The account's available funds after the transaction.
+    pub available_after: Decimal,
+    /// The account's held funds after the transaction.
+    pub held_after: Decimal,
+}
+
+/// Writes audit entries to a given writer in CSV format, one row per entry.
+pub fn write_csv<W: io::Write>(entries: &[AuditEntry], writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for entry in entries {
+        wtr.serialize(entry)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes audit entries to a given writer as a JSON array.
+pub fn write_json<W: io::Write>(entries: &[AuditEntry], writer: W) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer(writer, entries)?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::engine::PaymentEngine;
+use rs_accountant::generator;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+const NUM_CLIENTS: u16 = 1000;
+const NUM_TRANSACTIONS: u32 = 2_000_000;
+const SEED: u64 = 42;
+
+/// Compares ingestion throughput of the CSV and binary input formats on the same generated
+/// transactions. The transaction count may be given as the only argument.
+fn main() -> Result<(), Box<dyn Error>> {
+    let num_transactions = match std::env::args().nth(1) {
+        Some(arg) => arg.parse().map_err(|_| format!("invalid transaction count `{}`", arg))?,
+        None => NUM_TRANSACTIONS,
+    };
+
+    println!("Generating {} transactions...", num_transactions);
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let (transactions, _) = generator::generate(&mut rng, NUM_CLIENTS, num_transactions);
+    let mut csv = Vec::new();
+    generator::write_transactions(&transactions, &mut csv)?;
+    let mut binary = Vec::new();
+    generator::write_transactions_binary(&transactions, &mut binary)?;
+    drop(transactions);
+
+    let start = Instant::now();
+    let mut csv_engine = PaymentEngine::new();
+    csv_engine.process_transactions(csv.as_slice())?;
+    report("CSV", csv.len(), num_transactions, start.elapsed());
+
+    let start = Instant::now();
+    let mut binary_engine = PaymentEngine::new();
+    binary_engine.process_transactions_binary(binary.as_slice())?;
+    report("Binary", binary.len(), num_transactions, start.elapsed());
+
+    if !csv_engine.diff(&binary_engine).is_empty() {
+        return Err("the CSV and binary inputs produced different account states".into());
+    }
+    Ok(())
+}
+
+fn report(format: &str, bytes: usize, num_transactions: u32, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{:<6} {:>8.1} MB in {:>6.3}s: {:>6.1} MB/s, {:>10.0} transactions/s",
+        format,
+        bytes as f64 / 1e6,
+        seconds,
+        bytes as f64 / 1e6 / seconds,
+        f64::from(num_transactions) / seconds,
+    );
+}
@@ -1,38 +1,94 @@
 use rand::{seq::SliceRandom, Rng};
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
-use serde::Serialize;
-use rs_accountant::engine::TransactionType;
+use rs_accountant::engine::{InputTransaction, TransactionType};
+use rs_accountant::generator::{choose_transaction_type, TransactionWriter};
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
 
 const NUM_CLIENTS: u16 = 1000;
 const NUM_TRANSACTIONS: u32 = 35_000_000; // Approx. 1GB
 const OUTPUT_FILE: &str = "large_input.csv";
 
-#[derive(Debug, Serialize)]
-struct GenTransaction {
-    #[serde(rename = "type")]
-    transaction_type: TransactionType,
-    #[serde(rename = "client")]
-    client_id: u16,
-    #[serde(rename = "tx")]
-    tx_id: u32,
-    amount: Option<Decimal>,
+const USAGE: &str = "Usage: generate-stress-input [--clients <n>] [--transactions <n>] \
+                     [--min-amount <amount>] [--max-amount <amount>] [--scale <min>[-<max>]] \
+                     [--output <path>]";
+
+/// Generator settings, taken from the command line.
+struct Options {
+    num_clients: u16,
+    num_transactions: u32,
+    /// Bounds applied to all deposit and withdrawal amounts. When unset, deposits are below
+    /// 100 and withdrawals below 10.
+    min_amount: Option<Decimal>,
+    max_amount: Option<Decimal>,
+    /// The range of decimal places amounts are generated with.
+    scale: RangeInclusive<u32>,
+    output: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            num_clients: NUM_CLIENTS,
+            num_transactions: NUM_TRANSACTIONS,
+            min_amount: None,
+            max_amount: None,
+            scale: 2..=4,
+            output: OUTPUT_FILE.to_string(),
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("`{}` expects a value", arg))?;
+        let invalid = || format!("invalid value `{}` for `{}`", value, arg);
+        match arg.as_str() {
+            "--clients" => options.num_clients = value.parse().map_err(|_| invalid())?,
+            "--transactions" => options.num_transactions = value.parse().map_err(|_| invalid())?,
+            "--min-amount" => options.min_amount = Some(value.parse().map_err(|_| invalid())?),
+            "--max-amount" => options.max_amount = Some(value.parse().map_err(|_| invalid())?),
+            "--scale" => {
+                let (min, max) = value.split_once('-').unwrap_or((&value, &value));
+                let min = min.parse().map_err(|_| invalid())?;
+                let max = max.parse().map_err(|_| invalid())?;
+                if min > max {
+                    return Err(invalid());
+                }
+                options.scale = min..=max;
+            }
+            "--output" => options.output = value,
+            _ => return Err(format!("unknown argument `{}`", arg)),
+        }
+    }
+    if options.num_clients == 0 {
+        return Err("`--clients` must be at least 1".to_string());
+    }
+    Ok(options)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    println!("Generating stress test file: {}...", OUTPUT_FILE);
-    let file = File::create(OUTPUT_FILE)?;
-    let mut wtr = BufWriter::new(file);
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("{}", USAGE);
+            return Err("Invalid arguments".into());
+        }
+    };
 
-    writeln!(wtr, "type,client,tx,amount")?;
+    println!("Generating stress test file: {}...", options.output);
+    let file = File::create(&options.output)?;
+    let mut wtr = TransactionWriter::new(file);
 
     let mut rng = rand::thread_rng();
     let mut valid_tx_ids: Vec<u32> = Vec::new();
 
-    for tx_id in 1..=NUM_TRANSACTIONS {
-        let client_id = rng.gen_range(1..=NUM_CLIENTS);
+    for tx_id in 1..=options.num_transactions {
+        let client_id = rng.gen_range(1..=options.num_clients);
         let transaction_type = choose_transaction_type(&mut rng, &valid_tx_ids);
 
         let tx_id_for_dispute = if !valid_tx_ids.is_empty() {
@@ -40,60 +96,67 @@ fn main() -> Result<(), Box<dyn Error>> {
         } else {
             1 // Should not be hit after first deposit
         };
-        
-        match transaction_type {
+
+        let (tx_id, amount) = match transaction_type {
             TransactionType::Deposit => {
-                // Generate amounts with varying decimal precision (2-4 decimal places)
-                let scale = rng.gen_range(2..=4);
-                let max_value = 10_i64.pow(scale + 2); // Adjust range based on scale
-                let amount = Decimal::new(rng.gen_range(1..max_value), scale);
-                writeln!(wtr, "deposit,{},{},{}", client_id, tx_id, amount)?;
+                // Generate amounts with varying decimal precision (2-4 decimal places by default)
+                let amount = generate_amount(&mut rng, &options, 2)?;
                 if valid_tx_ids.len() < 1000 { // Keep the list of disputable txs small
                     valid_tx_ids.push(tx_id);
                 }
+                (tx_id, Some(amount))
             }
-            TransactionType::Withdrawal => {
-                // Generate amounts with varying decimal precision (2-4 decimal places)
-                let scale = rng.gen_range(2..=4);
-                let max_value = 10_i64.pow(scale + 1); // Smaller range for withdrawals
-                let amount = Decimal::new(rng.gen_range(1..max_value), scale);
-                writeln!(wtr, "withdrawal,{},{},{}", client_id, tx_id, amount)?;
-            }
-            TransactionType::Dispute => {
-                writeln!(wtr, "dispute,{},{},", client_id, tx_id_for_dispute)?;
-            }
-            TransactionType::Resolve => {
-                writeln!(wtr, "resolve,{},{},", client_id, tx_id_for_dispute)?;
-            }
-            TransactionType::Chargeback => {
-                 writeln!(wtr, "chargeback,{},{},", client_id, tx_id_for_dispute)?;
+            TransactionType::Withdrawal | TransactionType::Adjustment => {
+                // Smaller range for withdrawals unless bounds are given
+                (tx_id, Some(generate_amount(&mut rng, &options, 1)?))
             }
-        }
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Reversal => (tx_id_for_dispute, None),
+        };
+        wtr.write(&InputTransaction {
+            transaction_type,
+            client_id,
+            tx_id,
+            amount,
+            currency: None,
+            reference: None,
+            seq: None,
+        })?;
     }
 
     wtr.flush()?;
-    println!("Successfully generated {} transactions to {}.", NUM_TRANSACTIONS, OUTPUT_FILE);
+    println!(
+        "Successfully generated {} transactions to {}.",
+        options.num_transactions, options.output
+    );
     Ok(())
 }
 
-fn choose_transaction_type(rng: &mut impl Rng, valid_tx_ids: &[u32]) -> TransactionType {
-    if valid_tx_ids.is_empty() {
-        return TransactionType::Deposit;
-    }
+/// Generates a positive amount with a random scale from the configured range. Without
+/// configured bounds, amounts are below `10^magnitude`.
+fn generate_amount(
+    rng: &mut impl Rng,
+    options: &Options,
+    magnitude: u32,
+) -> Result<Decimal, String> {
+    let scale = rng.gen_range(options.scale.clone());
+    let unit = Decimal::new(1, scale);
+    let min = options.min_amount.unwrap_or(unit).max(unit);
+    let max = options
+        .max_amount
+        .unwrap_or_else(|| Decimal::from(10_i64.pow(magnitude)) - unit);
 
-    *[
-        TransactionType::Deposit,
-        TransactionType::Withdrawal,
-        TransactionType::Dispute,
-        TransactionType::Resolve,
-        TransactionType::Chargeback,
-    ]
-    .choose_weighted(rng, |item| match item {
-        TransactionType::Deposit => 40,
-        TransactionType::Withdrawal => 30,
-        TransactionType::Dispute => 10,
-        TransactionType::Resolve => 10,
-        TransactionType::Chargeback => 10,
-    })
-    .unwrap()
-} 
\ No newline at end of file
+    // Work in whole units of the chosen scale
+    let (Some(lo), Some(hi)) = ((min / unit).ceil().to_i64(), (max / unit).floor().to_i64()) else {
+        return Err("amount bounds are out of range".to_string());
+    };
+    if lo > hi {
+        return Err(format!(
+            "no amount with {} decimal places lies between {} and {}",
+            scale, min, max
+        ));
+    }
+    Ok(Decimal::new(rng.gen_range(lo..=hi), scale))
+}
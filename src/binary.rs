@@ -0,0 +1,78 @@
+use crate::amount::Amount;
+use crate::engine::{InputTransaction, TransactionType};
+use rust_decimal::Decimal;
+use std::io;
+
+/// The length in bytes of one encoded transaction.
+///
+/// Each record is laid out as a 1-byte transaction type code, the 2-byte client ID, the
+/// 4-byte transaction ID and the 16-byte amount as produced by `Decimal::serialize`. Integers
+/// are little-endian. An amount of all zero bytes means the transaction has no amount.
+/// Currencies are not encoded.
+pub const RECORD_LEN: usize = 23;
+
+/// The type codes used in the binary format, indexed by code.
+const TYPE_CODES: [TransactionType; 7] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Reversal,
+    TransactionType::Adjustment,
+];
+
+/// The result of decoding a single record.
+#[derive(Debug, Clone)]
+pub enum Decoded<A = Decimal> {
+    Transaction(InputTransaction<A>),
+    /// The record's type code names no known transaction type.
+    UnknownType(u8),
+}
+
+/// Encodes a transaction as a binary record.
+pub fn encode(tx: &InputTransaction) -> [u8; RECORD_LEN] {
+    let mut record = [0; RECORD_LEN];
+    record[0] = TYPE_CODES
+        .iter()
+        .position(|&transaction_type| transaction_type == tx.transaction_type)
+        .expect("every transaction type has a code") as u8;
+    record[1..3].copy_from_slice(&tx.client_id.to_le_bytes());
+    record[3..7].copy_from_slice(&tx.tx_id.to_le_bytes());
+    if let Some(amount) = tx.amount {
+        record[7..].copy_from_slice(&amount.serialize());
+    }
+    record
+}
+
+/// Decodes a binary record, converting its amount to the amount type `A`.
+pub fn decode<A: Amount>(record: &[u8; RECORD_LEN]) -> Decoded<A> {
+    let Some(&transaction_type) = TYPE_CODES.get(record[0] as usize) else {
+        return Decoded::UnknownType(record[0]);
+    };
+    let amount: [u8; 16] = record[7..].try_into().unwrap();
+    Decoded::Transaction(InputTransaction {
+        transaction_type,
+        client_id: u16::from_le_bytes([record[1], record[2]]),
+        tx_id: u32::from_le_bytes(record[3..7].try_into().unwrap()),
+        amount: (amount != [0; 16]).then(|| A::from_decimal(Decimal::deserialize(amount))),
+        currency: None,
+        reference: None,
+        seq: None,
+    })
+}
+
+/// Reads the next record into `buf`, returning the number of bytes read. Fewer than
+/// `RECORD_LEN` bytes are only returned at the end of the input.
+pub(crate) fn read_record<R: io::Read>(reader: &mut R, buf: &mut [u8; RECORD_LEN]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < RECORD_LEN {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
@@ -1,16 +1,30 @@
-use rust_decimal::Decimal;
+use crate::amount::Amount;
+use crate::audit::AuditEntry;
+use crate::binary::{self, Decoded};
+use crate::store::Store;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::de::{value, IntoDeserializer};
+use serde::ser::{SerializeSeq, Serializer};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
 
 /// A unique identifier for a client.
 pub type ClientId = u16;
 /// A unique identifier for a transaction.
 pub type TransactionId = u32;
 
-/// The dispute status of a transaction.
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The dispute status of a transaction. It is serialized in snake case, e.g. `charged_back`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DisputeStatus {
     /// Transaction has never been disputed
     NotDisputed,
@@ -20,10 +34,36 @@ pub enum DisputeStatus {
     Resolved,
     /// Transaction was disputed and charged back (resolved against client)
     ChargedBack,
+    /// Deposit was reversed by the processor and can no longer be disputed
+    Reversed,
+    /// Deposit was rejected because the account was locked. Only recorded when auditing, so
+    /// that disputes against it report why it cannot be disputed. It never moved any funds.
+    Rejected,
+}
+
+/// Returns whether a stored transaction's dispute status may change from `from` to `to` in a
+/// single operation. This is the dispute state machine:
+///
+/// - a dispute moves `NotDisputed` or `Resolved` to `Disputed`;
+/// - a resolve moves `Disputed` to `Resolved`;
+/// - a chargeback moves `Disputed` to `ChargedBack`;
+/// - a reversal moves `NotDisputed` or `Resolved` to `Reversed`.
+///
+/// `ChargedBack`, `Reversed` and `Rejected` are final. Leaving a status unchanged is always
+/// valid, since a rejected operation changes nothing.
+pub fn valid_transition(from: DisputeStatus, to: DisputeStatus) -> bool {
+    use DisputeStatus::*;
+    from == to
+        || matches!(
+            (from, to),
+            (NotDisputed | Resolved, Disputed)
+                | (Disputed, Resolved | ChargedBack)
+                | (NotDisputed | Resolved, Reversed)
+        )
 }
 
 /// The type of a transaction.
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -31,11 +71,37 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Cancels a prior deposit, e.g. one that bounced.
+    Reversal,
+    /// A manual correction whose amount may be positive or negative.
+    Adjustment,
+}
+
+impl FromStr for TransactionType {
+    type Err = UnknownTransactionType;
+
+    /// Parses a transaction type by the name it has in the input, e.g. `deposit`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deserializer: value::StrDeserializer<'_, value::Error> = s.into_deserializer();
+        Self::deserialize(deserializer).map_err(|_| UnknownTransactionType(s.to_string()))
+    }
+}
+
+/// An error returned when parsing a string that names no transaction type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTransactionType(pub String);
+
+impl fmt::Display for UnknownTransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown transaction type `{}`", self.0)
+    }
 }
 
+impl Error for UnknownTransactionType {}
+
 /// Represents a transaction read from the input CSV.
-#[derive(Debug, Deserialize, Clone)]
-pub struct InputTransaction {
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InputTransaction<A = Decimal> {
     /// The type of the transaction.
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
@@ -46,21 +112,474 @@ pub struct InputTransaction {
     #[serde(rename = "tx")]
     pub tx_id: TransactionId,
     /// The amount of the transaction, if applicable.
-    pub amount: Option<Decimal>,
+    pub amount: Option<A>,
+    /// The currency of the transaction, from the optional `currency` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// An opaque reference from the optional `reference` column. It plays no part in
+    /// accounting; the latest one applied to an account is echoed in the export.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// The producer's sequence number, from the optional `seq` column. Only checked with
+    /// `EngineConfig::enforce_seq_order`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+/// Which thousands separators input amounts may contain. Note that a CSV field containing a
+/// comma, e.g. `"1,234.50"`, must be quoted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeparatorPolicy {
+    /// Amounts are plain decimals with a `.` decimal point, e.g. `1234.50`.
+    #[default]
+    Plain,
+    /// Commas are thousands separators and are ignored, e.g. `1,234.50`.
+    CommaThousands,
+    /// Dots are thousands separators and are ignored, and a comma is the decimal point, e.g.
+    /// `1.234,50`.
+    DotThousands,
+}
+
+impl SeparatorPolicy {
+    /// Rewrites an amount as a plain decimal. Grouping is not checked, so `1,2,3` is `123`
+    /// under `CommaThousands`.
+    pub fn normalize(self, amount: &str) -> Cow<'_, str> {
+        match self {
+            SeparatorPolicy::Plain => Cow::Borrowed(amount),
+            SeparatorPolicy::CommaThousands if !amount.contains(',') => Cow::Borrowed(amount),
+            SeparatorPolicy::CommaThousands => Cow::Owned(amount.replace(',', "")),
+            SeparatorPolicy::DotThousands => Cow::Owned(
+                amount
+                    .chars()
+                    .filter(|&c| c != '.')
+                    .map(|c| if c == ',' { '.' } else { c })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Deserializes an amount from its text after normalizing its separators.
+fn parse_amount<A: Amount>(amount: &str, separators: SeparatorPolicy) -> Result<A, value::Error> {
+    let amount = separators.normalize(amount);
+    let deserializer: value::StrDeserializer<'_, value::Error> = amount.as_ref().into_deserializer();
+    A::deserialize(deserializer)
+}
+
+/// The positions of the transaction fields in the input, resolved once from the header row
+/// so that each row is read positionally instead of looking fields up by name.
+struct ColumnIndices {
+    transaction_type: usize,
+    client_id: usize,
+    tx_id: usize,
+    amount: Option<usize>,
+    currency: Option<usize>,
+    reference: Option<usize>,
+    seq: Option<usize>,
+}
+
+/// Why a row could not be read by `ColumnIndices::read`.
+enum RowError {
+    /// The `type` field names no known transaction type, given here.
+    UnknownType(String),
+    /// The named field is missing or invalid.
+    Malformed(&'static str),
+}
+
+impl ColumnIndices {
+    /// Resolves the field positions, or returns the first required column that is missing.
+    fn from_headers(headers: &csv::StringRecord) -> Result<Self, &'static str> {
+        let position = |column| headers.iter().position(|header| header == column);
+        Ok(Self {
+            transaction_type: position("type").ok_or("type")?,
+            client_id: position("client").ok_or("client")?,
+            tx_id: position("tx").ok_or("tx")?,
+            amount: position("amount"),
+            currency: position("currency"),
+            reference: position("reference"),
+            seq: position("seq"),
+        })
+    }
+
+    /// Reads a transaction from a row, with the same rules as deserializing it by header
+    /// name: empty optional fields, or missing optional columns, are `None`. The amount may
+    /// contain separators as `separators` allows.
+    fn read<A: Amount>(
+        &self,
+        record: &csv::StringRecord,
+        separators: SeparatorPolicy,
+    ) -> Result<InputTransaction<A>, RowError> {
+        let field = |index: usize, name| record.get(index).ok_or(RowError::Malformed(name));
+        let optional = |index: Option<usize>| index.and_then(|index| record.get(index)).filter(|value| !value.is_empty());
+
+        let transaction_type = field(self.transaction_type, "type")?
+            .parse::<TransactionType>()
+            .map_err(|err| RowError::UnknownType(err.0))?;
+        let client_id = field(self.client_id, "client")?.parse().map_err(|_| RowError::Malformed("client"))?;
+        let tx_id = field(self.tx_id, "tx")?.parse().map_err(|_| RowError::Malformed("tx"))?;
+        let amount = match optional(self.amount) {
+            Some(value) => Some(parse_amount(value, separators).map_err(|_| RowError::Malformed("amount"))?),
+            None => None,
+        };
+        let seq = match optional(self.seq) {
+            Some(value) => Some(value.parse().map_err(|_| RowError::Malformed("seq"))?),
+            None => None,
+        };
+        Ok(InputTransaction {
+            transaction_type,
+            client_id,
+            tx_id,
+            amount,
+            currency: optional(self.currency).map(String::from),
+            reference: optional(self.reference).map(String::from),
+            seq,
+        })
+    }
+}
+
+/// Creates the CSV reader used for all transaction input. Blank lines and lines starting
+/// with `#` are skipped.
+fn csv_reader<R: io::Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(reader)
+}
+
+/// Processes several CSV files into a single engine, in the given order.
+///
+/// Files are applied one after another and rows within each file in order, so transactions
+/// in later files may reference those in earlier ones, e.g. a dispute of a deposit made on a
+/// previous day. A path of `-` reads from standard input.
+pub fn process_files(paths: &[PathBuf]) -> Result<PaymentEngine, AccountantError> {
+    process_files_with_config(paths, EngineConfig::default())
+}
+
+/// Processes several CSV files into a single engine with the given configuration, like
+/// `process_files`.
+pub fn process_files_with_config(
+    paths: &[PathBuf],
+    config: EngineConfig,
+) -> Result<PaymentEngine, AccountantError> {
+    let mut engine = PaymentEngine::with_config(config);
+    for path in paths {
+        let result = if path.as_os_str() == "-" {
+            engine.process_transactions(io::stdin().lock())
+        } else {
+            File::open(path)
+                .map_err(AccountantError::from)
+                .and_then(|file| engine.process_transactions(file))
+        };
+        result.map_err(|err| AccountantError::File { path: path.clone(), source: Box::new(err) })?;
+    }
+    Ok(engine)
+}
+
+/// Processes a directory of pre-sharded CSV files, such as `client_0.csv`, `client_1.csv`,
+/// one worker thread per file, and merges the results.
+///
+/// Each file must hold a disjoint set of clients, since files are processed independently
+/// and a dispute cannot reference a transaction in another file. Every `.csv` file in the
+/// directory is processed; results are merged in file name order. A worker that panics,
+/// e.g. on a failed `HeldCheck::Panic`, is reported as `AccountantError::WorkerPanicked`
+/// for its file.
+pub fn process_sharded_dir(dir: &Path) -> Result<PaymentEngine, AccountantError> {
+    process_sharded_dir_with_config(dir, EngineConfig::default())
+}
+
+/// Processes a directory of pre-sharded CSV files with the given configuration, like
+/// `process_sharded_dir`.
+pub fn process_sharded_dir_with_config(
+    dir: &Path,
+    config: EngineConfig,
+) -> Result<PaymentEngine, AccountantError> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let shards = thread::scope(|scope| {
+        let workers: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let config = config.clone();
+                scope.spawn(move || {
+                    let mut engine = PaymentEngine::with_config(config);
+                    File::open(path)
+                        .map_err(AccountantError::from)
+                        .and_then(|file| engine.process_transactions(file))
+                        .map(|()| engine)
+                        .map_err(|err| AccountantError::File { path: path.clone(), source: Box::new(err) })
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .zip(&paths)
+            .map(|(worker, path)| {
+                worker.join().unwrap_or_else(|_| {
+                    let source = Box::new(AccountantError::WorkerPanicked);
+                    Err(AccountantError::File { path: path.clone(), source })
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut engine = PaymentEngine::with_config(config);
+    for shard in shards {
+        engine.merge(shard?)?;
+    }
+    Ok(engine)
+}
+
+/// The cause of a `ProcessError`.
+#[derive(Debug)]
+pub enum ProcessErrorKind {
+    /// The input is missing a required column.
+    MissingColumn(&'static str),
+    /// The row could not be read as CSV.
+    Csv(csv::Error),
+    /// The row's named field is missing or invalid.
+    InvalidField(&'static str),
+    /// The row's `type` field names no known transaction type.
+    UnknownType(String),
+    /// The transaction was rejected by the engine.
+    Rejected(RejectReason),
+}
+
+/// An error that halted strict processing, identifying the offending input line.
+#[derive(Debug)]
+pub struct ProcessError {
+    /// The 1-based line number in the input.
+    pub line: u64,
+    /// What went wrong on that line.
+    pub kind: ProcessErrorKind,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ProcessErrorKind::MissingColumn(column) => {
+                write!(f, "line {}: input is missing the required `{}` column", self.line, column)
+            }
+            ProcessErrorKind::Csv(err) => write!(f, "line {}: malformed row: {}", self.line, err),
+            ProcessErrorKind::InvalidField(field) => {
+                write!(f, "line {}: malformed row: invalid `{}` field", self.line, field)
+            }
+            ProcessErrorKind::UnknownType(value) => {
+                write!(f, "line {}: unknown transaction type `{}`", self.line, value)
+            }
+            ProcessErrorKind::Rejected(reason) => {
+                write!(f, "line {}: transaction rejected: {}", self.line, reason)
+            }
+        }
+    }
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ProcessErrorKind::MissingColumn(_)
+            | ProcessErrorKind::InvalidField(_)
+            | ProcessErrorKind::UnknownType(_) => None,
+            ProcessErrorKind::Csv(err) => Some(err),
+            ProcessErrorKind::Rejected(reason) => Some(reason),
+        }
+    }
+}
+
+/// The cause of a `ReplayError`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayErrorKind {
+    /// The transaction was rejected when replayed, although the log records it as applied.
+    Rejected(RejectReason),
+    /// The account's available and held funds before the transaction differ from the logged
+    /// ones, meaning an earlier entry is missing or out of order.
+    BalanceBeforeMismatch { logged: (Decimal, Decimal), replayed: (Decimal, Decimal) },
+    /// The account's available and held funds after the transaction differ from the logged
+    /// ones.
+    BalanceAfterMismatch { logged: (Decimal, Decimal), replayed: (Decimal, Decimal) },
+}
+
+/// An error that halted replaying an audit log, identifying the offending entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayError {
+    /// The 0-based index of the entry in the log.
+    pub index: usize,
+    /// The ID of the entry's transaction.
+    pub tx_id: TransactionId,
+    /// What went wrong replaying it.
+    pub kind: ReplayErrorKind,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "audit entry {} (tx {}): ", self.index, self.tx_id)?;
+        match &self.kind {
+            ReplayErrorKind::Rejected(reason) => write!(f, "transaction rejected on replay: {}", reason),
+            ReplayErrorKind::BalanceBeforeMismatch { logged, replayed } => write!(
+                f,
+                "logged balance before is {}/{} but replayed balance is {}/{}",
+                logged.0, logged.1, replayed.0, replayed.1
+            ),
+            ReplayErrorKind::BalanceAfterMismatch { logged, replayed } => write!(
+                f,
+                "logged balance after is {}/{} but replayed balance is {}/{}",
+                logged.0, logged.1, replayed.0, replayed.1
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ReplayErrorKind::Rejected(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// An error from processing input or exporting results.
+#[derive(Debug)]
+pub enum AccountantError {
+    /// The input could not be read as CSV.
+    Csv(csv::Error),
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The input is missing a required column.
+    MissingColumn(&'static str),
+    /// A transaction was rejected in a way that halts processing, such as a failed
+    /// `held_check`.
+    Rejected { tx_id: TransactionId, reason: RejectReason },
+    /// Strict processing stopped at an offending line.
+    Process(ProcessError),
+    /// Exporting accounts failed.
+    Export(ExportError),
+    /// Merging the engines of separately processed inputs failed.
+    Merge(MergeError),
+    /// An error occurred in the given input file.
+    File { path: PathBuf, source: Box<AccountantError> },
+    /// The worker thread processing an input panicked.
+    WorkerPanicked,
+}
+
+impl fmt::Display for AccountantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountantError::Csv(err) => err.fmt(f),
+            AccountantError::Io(err) => err.fmt(f),
+            AccountantError::MissingColumn(column) => {
+                write!(f, "input is missing the required `{}` column", column)
+            }
+            AccountantError::Rejected { tx_id, reason } => write!(f, "transaction {}: {}", tx_id, reason),
+            AccountantError::Process(err) => err.fmt(f),
+            AccountantError::Export(err) => err.fmt(f),
+            AccountantError::Merge(err) => err.fmt(f),
+            AccountantError::File { path, source } => write!(f, "{}: {}", path.display(), source),
+            AccountantError::WorkerPanicked => f.write_str("worker thread panicked"),
+        }
+    }
+}
+
+impl Error for AccountantError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AccountantError::Csv(err) => Some(err),
+            AccountantError::Io(err) => Some(err),
+            AccountantError::MissingColumn(_) => None,
+            AccountantError::Rejected { reason, .. } => Some(reason),
+            AccountantError::Process(err) => Some(err),
+            AccountantError::Export(err) => Some(err),
+            AccountantError::Merge(err) => Some(err),
+            AccountantError::File { source, .. } => Some(source.as_ref()),
+            AccountantError::WorkerPanicked => None,
+        }
+    }
+}
+
+impl From<csv::Error> for AccountantError {
+    fn from(err: csv::Error) -> Self {
+        AccountantError::Csv(err)
+    }
+}
+
+impl From<io::Error> for AccountantError {
+    fn from(err: io::Error) -> Self {
+        AccountantError::Io(err)
+    }
+}
+
+impl From<ProcessError> for AccountantError {
+    fn from(err: ProcessError) -> Self {
+        AccountantError::Process(err)
+    }
+}
+
+impl From<ExportError> for AccountantError {
+    fn from(err: ExportError) -> Self {
+        AccountantError::Export(err)
+    }
+}
+
+impl From<MergeError> for AccountantError {
+    fn from(err: MergeError) -> Self {
+        AccountantError::Merge(err)
+    }
 }
 
 /// Represents a client account for serialization to CSV.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct OutputAccount {
     #[serde(rename = "client")]
-    id: ClientId,
+    pub(crate) id: ClientId,
     #[serde(with = "serde_decimal")]
-    available: Decimal,
+    pub(crate) available: Decimal,
     #[serde(with = "serde_decimal")]
-    held: Decimal,
+    pub(crate) held: Decimal,
     #[serde(with = "serde_decimal")]
-    total: Decimal,
-    locked: bool,
+    pub(crate) total: Decimal,
+    pub(crate) locked: bool,
+    /// Only set, for every account, when some account has a reference to export.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reference: Option<String>,
+}
+
+/// Represents a stored transaction for serialization to CSV.
+#[derive(Debug, Serialize)]
+struct OutputTransaction {
+    tx: TransactionId,
+    client: ClientId,
+    #[serde(with = "serde_decimal")]
+    amount: Decimal,
+    dispute_status: DisputeStatus,
+}
+
+/// The column names of the transaction output, in order.
+const TRANSACTION_COLUMNS: [&str; 4] = ["tx", "client", "amount", "dispute_status"];
+
+/// The column names of the account output, in order.
+const OUTPUT_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Formats a monetary amount the way it appears in all engine output.
+///
+/// Trailing zeros are removed and the result is padded to at least four decimal places;
+/// amounts with more than four significant decimal places keep their full precision.
+pub fn format_amount(amount: Decimal) -> String {
+    // Normalize to remove trailing zeros for consistent formatting.
+    let normalized = amount.normalize();
+    // Per requirements, output should have a precision of *at least* four places.
+    // If the number has fewer than 4 decimal places, format it to 4.
+    // Otherwise, preserve its full precision.
+    if normalized.scale() < 4 {
+        format!("{:.4}", normalized)
+    } else {
+        normalized.to_string()
+    }
 }
 
 mod serde_decimal {
@@ -71,17 +590,7 @@ mod serde_decimal {
     where
         S: Serializer,
     {
-        // Normalize to remove trailing zeros for consistent formatting.
-        let normalized = val.normalize();
-        // Per requirements, output should have a precision of *at least* four places.
-        // If the number has fewer than 4 decimal places, format it to 4.
-        // Otherwise, preserve its full precision.
-        let formatted = if normalized.scale() < 4 {
-            format!("{:.4}", normalized)
-        } else {
-            normalized.to_string()
-        };
-        serializer.serialize_str(&formatted)
+        serializer.serialize_str(&super::format_amount(*val))
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
@@ -93,220 +602,2759 @@ mod serde_decimal {
     }
 }
 
-impl<'a> From<&'a Account> for OutputAccount {
-    fn from(account: &'a Account) -> Self {
-        Self {
-            id: account.id,
-            available: account.available,
-            held: account.held,
-            total: account.total(),
-            locked: account.locked,
-        }
-    }
-}
-
-/// Represents the state of a client's account.
+/// An error that occurred while exporting accounts.
 #[derive(Debug)]
-pub struct Account {
-    pub id: ClientId,
-    pub available: Decimal,
-    pub held: Decimal,
-    pub locked: bool,
+pub enum ExportError {
+    /// An account could not be serialized.
+    Serialize { client_id: ClientId, source: csv::Error },
+    /// Writing to the underlying writer failed, for the given client if known.
+    Io { client_id: Option<ClientId>, source: io::Error },
+    /// Flushing buffered output to the underlying writer failed.
+    Flush(io::Error),
+    /// A client's total funds overflow the amount type, or adding the client's balances to a
+    /// report's totals row overflows.
+    TotalOverflow(ClientId),
 }
 
-impl Account {
-    /// Creates a new, empty account for a client.
-    pub fn new(id: ClientId) -> Self {
-        Self {
-            id,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            locked: false,
+impl ExportError {
+    /// Classifies an error from serializing a client's account as a serialization or I/O
+    /// failure.
+    fn from_csv(client_id: ClientId, err: csv::Error) -> Self {
+        if !err.is_io_error() {
+            return ExportError::Serialize { client_id, source: err };
+        }
+        match err.into_kind() {
+            csv::ErrorKind::Io(source) => ExportError::Io { client_id: Some(client_id), source },
+            _ => unreachable!("checked by is_io_error"),
         }
     }
 
-    /// Calculates the total funds in the account (available + held).
-    pub fn total(&self) -> Decimal {
-        self.available + self.held
+    /// Converts an error from writing JSON, which can only fail on I/O since account output
+    /// always serializes.
+    fn from_json(client_id: Option<ClientId>, err: serde_json::Error) -> Self {
+        ExportError::Io { client_id, source: err.into() }
     }
-}
 
-/// Represents a deposit or withdrawal transaction that is stored for potential disputes.
-#[derive(Debug)]
-pub struct StoredTransaction {
-    pub client_id: ClientId,
-    pub amount: Decimal,
-    pub dispute_status: DisputeStatus,
+    /// Converts an error from writing a fixed record, such as a header, which can only fail
+    /// on I/O.
+    fn from_csv_write(err: csv::Error) -> Self {
+        match err.into_kind() {
+            csv::ErrorKind::Io(source) => ExportError::Io { client_id: None, source },
+            kind => unreachable!("writing a fixed record cannot fail with {:?}", kind),
+        }
+    }
 }
 
-/// The main payment processing engine.
-pub struct PaymentEngine {
-    /// A map of client IDs to their accounts.
-    pub accounts: HashMap<ClientId, Account>,
-    /// A map of transaction IDs to their details, for dispute handling.
-    pub transactions: HashMap<TransactionId, StoredTransaction>,
+/// The order in which `export_accounts_sorted_by` writes accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// Ascending client ID, as `export_accounts` writes them.
+    #[default]
+    ClientId,
+    /// Descending total funds, computed as `EngineConfig::total_mode` specifies.
+    TotalDesc,
+    /// Descending held funds.
+    HeldDesc,
+    /// Descending available funds.
+    AvailableDesc,
 }
 
-impl Default for PaymentEngine {
-    fn default() -> Self {
-        Self::new()
-    }
+/// The CSV dialect used for account output.
+#[derive(Debug, Clone)]
+pub struct CsvFormat {
+    /// The field delimiter.
+    pub delimiter: u8,
+    /// When fields are quoted.
+    pub quote_style: csv::QuoteStyle,
+    /// The header row, naming the client, available, held, total and locked columns in that
+    /// order.
+    pub headers: [String; 5],
 }
 
-impl PaymentEngine {
-    /// Creates a new `PaymentEngine`.
-    pub fn new() -> Self {
+impl Default for CsvFormat {
+    /// Comma-delimited, quoting only fields that need it, with the standard
+    /// `client,available,held,total,locked` header.
+    fn default() -> Self {
         Self {
-            accounts: HashMap::new(),
-            transactions: HashMap::new(),
+            delimiter: b',',
+            quote_style: csv::QuoteStyle::Necessary,
+            headers: OUTPUT_COLUMNS.map(String::from),
         }
     }
+}
 
-    /// Processes all transactions from a given reader and updates account states.
-    ///
-    /// Transactions are expected to be in CSV format. Invalid transactions are ignored.
-    pub fn process_transactions<R: io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
-        let mut rdr = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_reader(reader);
-
-        for tx in rdr.deserialize::<InputTransaction>().flatten() {
-            match tx.transaction_type {
-                TransactionType::Deposit => self.handle_deposit(tx),
-                TransactionType::Withdrawal => self.handle_withdrawal(tx),
-                TransactionType::Dispute => self.handle_dispute(tx),
-                TransactionType::Resolve => self.handle_resolve(tx),
-                TransactionType::Chargeback => self.handle_chargeback(tx),
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Serialize { client_id, source } => {
+                write!(f, "failed to serialize account for client {}: {}", client_id, source)
+            }
+            ExportError::Io { client_id: Some(client_id), source } => {
+                write!(f, "failed to write account for client {}: {}", client_id, source)
+            }
+            ExportError::Io { client_id: None, source } => write!(f, "failed to write output: {}", source),
+            ExportError::Flush(source) => write!(f, "failed to flush output: {}", source),
+            ExportError::TotalOverflow(client_id) => {
+                write!(f, "total funds of client {} overflow", client_id)
             }
         }
-        Ok(())
     }
+}
 
-    /// Handles a deposit transaction.
-    /// Increases the client's available funds and records the transaction.
-    /// Ignores deposits to locked accounts or with non-positive amounts.
-    pub fn handle_deposit(&mut self, tx: InputTransaction) {
-        let Some(amount) = tx.amount else { return };
-        if amount <= Decimal::ZERO {
-            return;
+impl Error for ExportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExportError::Serialize { source, .. } => Some(source),
+            ExportError::Io { source, .. } => Some(source),
+            ExportError::Flush(source) => Some(source),
+            ExportError::TotalOverflow(_) => None,
         }
+    }
+}
 
-        let account = self
-            .accounts
-            .entry(tx.client_id)
-            .or_insert_with(|| Account::new(tx.client_id));
-        if account.locked {
-            return;
-        }
+impl OutputAccount {
+    /// Converts an account, computing its total as `total_mode` specifies. Fails if the total
+    /// overflows.
+    pub fn with_total_mode<A: Amount>(account: &Account<A>, total_mode: TotalMode) -> Result<Self, ExportError> {
+        let total = account
+            .checked_total_with(total_mode)
+            .ok_or(ExportError::TotalOverflow(account.id))?;
+        Ok(Self {
+            id: account.id,
+            available: account.available.to_decimal(),
+            held: account.held.to_decimal(),
+            total: total.to_decimal(),
+            locked: account.locked,
+            reference: None,
+        })
+    }
+}
 
-        account.available += amount;
-        self.transactions.insert(
-            tx.tx_id,
-            StoredTransaction {
-                client_id: tx.client_id,
-                amount,
-                dispute_status: DisputeStatus::NotDisputed,
-            },
-        );
+impl<'a, A: Amount> TryFrom<&'a Account<A>> for OutputAccount {
+    type Error = ExportError;
+
+    /// Converts an account with its gross total. Fails if the total overflows.
+    fn try_from(account: &'a Account<A>) -> Result<Self, Self::Error> {
+        Self::with_total_mode(account, TotalMode::Gross)
     }
+}
 
-    /// Handles a withdrawal transaction.
-    /// Decreases the client's available funds if sufficient funds are available.
-    /// Ignores withdrawals from locked accounts or with non-positive amounts.
-    pub fn handle_withdrawal(&mut self, tx: InputTransaction) {
-        let Some(amount) = tx.amount else { return };
-        if amount <= Decimal::ZERO {
-            return;
-        }
+/// How an account's total funds are computed for display and export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotalMode {
+    /// Available plus held funds.
+    #[default]
+    Gross,
+    /// Available plus held funds, less fees accrued but not yet collected. This is what the
+    /// client can actually claim.
+    NetOfPendingFees,
+}
 
-        let account = self
-            .accounts
-            .entry(tx.client_id)
-            .or_insert_with(|| Account::new(tx.client_id));
-        if account.locked || account.available < amount {
-            return;
-        }
+/// Engine-wide monetary aggregates across all accounts, for a balance-sheet view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetPosition<A = Decimal> {
+    /// The sum of all accounts' available funds.
+    pub available: A,
+    /// The sum of all accounts' held funds.
+    pub held: A,
+    /// The total funds of unlocked accounts.
+    pub unlocked: A,
+    /// The total funds of locked accounts.
+    pub locked: A,
+    /// The total funds of all accounts, i.e. `available + held` and `unlocked + locked`.
+    pub total: A,
+}
 
-        account.available -= amount;
-        self.transactions.insert(
-            tx.tx_id,
-            StoredTransaction {
-                client_id: tx.client_id,
-                amount,
-                dispute_status: DisputeStatus::NotDisputed,
-            },
-        );
-    }
+/// A summary of an account's state, e.g. for a dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    /// The account is unlocked and has no transaction under dispute.
+    Healthy,
+    /// The account is unlocked and has at least one transaction under dispute.
+    HasActiveDispute,
+    /// The account is locked.
+    Frozen,
+}
 
-    /// Handles a dispute transaction.
-    /// Moves funds from available to held for the disputed transaction.
-    /// The referenced transaction must exist and not be currently disputed or charged back.
-    pub fn handle_dispute(&mut self, tx: InputTransaction) {
-        let Some(disputed_tx) = self.transactions.get_mut(&tx.tx_id) else { return };
-        if disputed_tx.client_id != tx.client_id {
-            return;
-        }
+/// Represents the state of a client's account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account<A = Decimal> {
+    pub id: ClientId,
+    pub available: A,
+    pub held: A,
+    pub locked: bool,
+    /// The currency of the account, established by its first deposit that names one.
+    pub currency: Option<String>,
+    /// Fees charged to the account but not yet collected. Only accrues when
+    /// `EngineConfig::accrue_fees` is set.
+    pub pending_fees: A,
+    /// The reference of the latest applied transaction that carried one.
+    pub last_reference: Option<String>,
+    /// The number of disputes applied to the account, counted against
+    /// `EngineConfig::max_disputes_per_client`.
+    #[serde(default)]
+    pub disputes: u32,
+}
 
-        let Some(account) = self.accounts.get_mut(&tx.client_id) else { return };
-        if account.locked 
-            || disputed_tx.dispute_status == DisputeStatus::Disputed 
-            || disputed_tx.dispute_status == DisputeStatus::ChargedBack {
-            return;
+impl<A: Amount> Account<A> {
+    /// Creates a new, empty account for a client.
+    pub fn new(id: ClientId) -> Self {
+        Self {
+            id,
+            available: A::ZERO,
+            held: A::ZERO,
+            locked: false,
+            currency: None,
+            pending_fees: A::ZERO,
+            last_reference: None,
+            disputes: 0,
         }
-
-        account.available -= disputed_tx.amount;
-        account.held += disputed_tx.amount;
-        disputed_tx.dispute_status = DisputeStatus::Disputed;
     }
 
-    /// Handles a resolve transaction.
-    /// Moves funds from held back to available, resolving the dispute.
-    /// The referenced transaction must exist and be under dispute.
-    pub fn handle_resolve(&mut self, tx: InputTransaction) {
-        let Some(disputed_tx) = self.transactions.get_mut(&tx.tx_id) else { return };
-        if disputed_tx.client_id != tx.client_id || disputed_tx.dispute_status != DisputeStatus::Disputed {
-            return;
+    /// Returns whether a transaction in the given currency may be applied to the account.
+    /// Transactions without a currency, and accounts without an established one, accept any.
+    pub fn accepts_currency(&self, currency: Option<&str>) -> bool {
+        match (self.currency.as_deref(), currency) {
+            (Some(established), Some(currency)) => established == currency,
+            _ => true,
         }
+    }
 
-        let Some(account) = self.accounts.get_mut(&tx.client_id) else { return };
-        if account.locked {
-            return;
+    /// Classifies the account, given whether any of its transactions is under dispute. A
+    /// locked account is frozen regardless.
+    pub fn status(&self, has_active_dispute: bool) -> AccountStatus {
+        if self.locked {
+            AccountStatus::Frozen
+        } else if has_active_dispute {
+            AccountStatus::HasActiveDispute
+        } else {
+            AccountStatus::Healthy
         }
+    }
 
-        account.available += disputed_tx.amount;
-        account.held -= disputed_tx.amount;
-        disputed_tx.dispute_status = DisputeStatus::Resolved;
+    /// Returns whether the account's available funds are negative, e.g. after a dispute of
+    /// funds that were already withdrawn.
+    pub fn is_overdrawn(&self) -> bool {
+        self.available < A::ZERO
     }
 
-    /// Handles a chargeback transaction.
-    /// Moves funds from held to withdrawn and freezes the client's account.
-    /// The referenced transaction must exist and be under dispute.
-    pub fn handle_chargeback(&mut self, tx: InputTransaction) {
-        let Some(disputed_tx) = self.transactions.get_mut(&tx.tx_id) else { return };
-        if disputed_tx.client_id != tx.client_id || disputed_tx.dispute_status != DisputeStatus::Disputed {
-            return;
-        }
+    /// Calculates the total funds in the account (available + held).
+    ///
+    /// Panics if the sum overflows; see `checked_total` for a fallible version.
+    pub fn total(&self) -> A {
+        self.available + self.held
+    }
 
-        let Some(account) = self.accounts.get_mut(&tx.client_id) else { return };
-        if account.locked {
-            return;
+    /// Calculates the total funds in the account, or `None` if the sum overflows.
+    pub fn checked_total(&self) -> Option<A> {
+        self.available.checked_add(self.held)
+    }
+
+    /// Calculates the total funds in the account as `mode` specifies, or `None` if the
+    /// calculation overflows.
+    pub fn checked_total_with(&self, mode: TotalMode) -> Option<A> {
+        let total = self.checked_total()?;
+        match mode {
+            TotalMode::Gross => Some(total),
+            TotalMode::NetOfPendingFees => total.checked_sub(self.pending_fees),
         }
+    }
 
-        account.held -= disputed_tx.amount;
-        account.locked = true;
-        disputed_tx.dispute_status = DisputeStatus::ChargedBack;
+    /// Returns a copy of the account's current state.
+    pub fn snapshot(&self) -> AccountSnapshot<A> {
+        AccountSnapshot {
+            client_id: self.id,
+            available: self.available,
+            held: self.held,
+            total: self.total(),
+            locked: self.locked,
+        }
     }
+}
 
-    /// Writes the final state of all accounts to a given writer in CSV format.
-    pub fn export_accounts<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
-        let mut wtr = csv::Writer::from_writer(writer);
-        let mut accounts: Vec<_> = self.accounts.values().collect();
-        accounts.sort_by_key(|a| a.id);
+/// A point-in-time copy of a client's account state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountSnapshot<A = Decimal> {
+    pub client_id: ClientId,
+    pub available: A,
+    pub held: A,
+    pub total: A,
+    pub locked: bool,
+}
 
-        for account in accounts {
-            wtr.serialize(OutputAccount::from(account))?;
-        }
-        wtr.flush()?;
-        Ok(())
+/// How a client's state differs between two engines, as reported by `PaymentEngine::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDiff<A = Decimal> {
+    pub client_id: ClientId,
+    /// The client's account in the engine `diff` was called on, if it has one.
+    pub left: Option<AccountSnapshot<A>>,
+    /// The client's account in the other engine, if it has one.
+    pub right: Option<AccountSnapshot<A>>,
+    /// The client's transactions whose dispute status differs, by ID, with the status in
+    /// each engine or `None` where the transaction is not stored.
+    pub dispute_statuses: Vec<(TransactionId, Option<DisputeStatus>, Option<DisputeStatus>)>,
+}
+
+/// Represents a deposit, withdrawal or adjustment transaction that is stored for potential
+/// disputes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredTransaction<A = Decimal> {
+    pub client_id: ClientId,
+    /// Whether this is a deposit, a withdrawal or an adjustment.
+    pub transaction_type: TransactionType,
+    /// The amount credited to or debited from the client, excluding any fee. For adjustments
+    /// this is the signed change to the client's available funds.
+    pub amount: A,
+    /// The fee charged for the transaction.
+    pub fee: A,
+    /// The funds currently held for the transaction while it is disputed.
+    pub held: A,
+    pub dispute_status: DisputeStatus,
+}
+
+impl<A: Amount> StoredTransaction<A> {
+    /// Returns the amount moved into held funds while the transaction is disputed.
+    pub fn disputed_amount(&self) -> A {
+        self.amount.abs()
+    }
+
+    /// Returns whether the transaction took funds from the client: a withdrawal or a negative
+    /// adjustment. Disputing one holds the debited funds for the client rather than taking
+    /// more from their available funds.
+    pub fn is_debit(&self) -> bool {
+        self.transaction_type == TransactionType::Withdrawal || self.amount < A::ZERO
+    }
+}
+
+/// The reason a transaction was rejected by the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// A deposit or withdrawal had no amount.
+    MissingAmount,
+    /// A deposit or withdrawal had a zero or negative amount.
+    NonPositiveAmount,
+    /// A deposit was not large enough to cover its fee.
+    AmountBelowFee,
+    /// The client's account is locked.
+    AccountLocked,
+    /// A withdrawal exceeded the client's available funds.
+    InsufficientFunds,
+    /// The referenced transaction does not exist.
+    UnknownTransaction,
+    /// The referenced transaction belongs to a different client.
+    ClientMismatch,
+    /// The client has no account.
+    UnknownClient,
+    /// The referenced transaction is not in a state that allows the operation.
+    InvalidDisputeStatus,
+    /// A dispute referenced a transaction that is already under dispute, e.g. a duplicate
+    /// dispute row.
+    AlreadyDisputed,
+    /// A resolve or chargeback referenced a transaction that was never disputed.
+    NotDisputed,
+    /// A resolve or chargeback referenced a transaction whose dispute was already resolved.
+    AlreadyResolved,
+    /// A resolve or chargeback referenced a transaction that was already charged back.
+    AlreadyChargedBack,
+    /// A dispute or reversal carried an amount in strict mode.
+    UnexpectedAmount,
+    /// A reversal referenced a transaction that is not a deposit.
+    NotADeposit,
+    /// An adjustment had an amount of zero.
+    ZeroAmount,
+    /// A partial resolve exceeded the funds held for the disputed transaction.
+    ExceedsHeldAmount,
+    /// A dispute would raise the client's held funds above `max_held_per_client`.
+    HeldCeilingExceeded,
+    /// The transaction would overflow a balance.
+    Overflow,
+    /// A resolve or chargeback would release more than the account's held funds, leaving them
+    /// negative. This indicates inconsistent engine state.
+    InsufficientHeldFunds,
+    /// The referenced deposit was itself rejected because the account was locked.
+    RejectedDeposit,
+    /// After the transaction, the engine-wide held funds exceeded the engine-wide net
+    /// deposits. See `EngineConfig::held_check`.
+    HeldExceedsNetDeposits,
+    /// The transaction's currency differs from the account's established currency.
+    CurrencyMismatch,
+    /// Storing the transaction would exceed `EngineConfig::max_transactions`.
+    TransactionLimitExceeded,
+    /// A deposit or withdrawal amount exceeded `EngineConfig::max_amount`.
+    AmountAboveMaximum,
+    /// A deposit, withdrawal or adjustment reused the ID of a charged-back transaction. See
+    /// `EngineConfig::reused_id_policy`.
+    ChargedBackIdReused,
+    /// The transaction's sequence number is below one already seen for the client, e.g.
+    /// because it was replayed. See `EngineConfig::enforce_seq_order`.
+    OutOfSequence,
+    /// The client already had `EngineConfig::max_disputes_per_client` disputes applied.
+    DisputeLimitExceeded,
+}
+
+impl RejectReason {
+    /// Returns whether the rejection stops `process_transactions` and the other processing
+    /// loops with an error rather than ignoring the transaction.
+    pub fn halts_processing(&self) -> bool {
+        matches!(self, RejectReason::HeldExceedsNetDeposits | RejectReason::TransactionLimitExceeded)
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            RejectReason::MissingAmount => "transaction has no amount",
+            RejectReason::NonPositiveAmount => "amount must be positive",
+            RejectReason::AmountBelowFee => "amount does not cover the fee",
+            RejectReason::AccountLocked => "account is locked",
+            RejectReason::InsufficientFunds => "insufficient available funds",
+            RejectReason::UnknownTransaction => "referenced transaction does not exist",
+            RejectReason::ClientMismatch => "referenced transaction belongs to another client",
+            RejectReason::UnknownClient => "client has no account",
+            RejectReason::InvalidDisputeStatus => "transaction is not in a valid dispute state",
+            RejectReason::AlreadyDisputed => "transaction is already disputed",
+            RejectReason::NotDisputed => "transaction is not under dispute",
+            RejectReason::AlreadyResolved => "dispute was already resolved",
+            RejectReason::AlreadyChargedBack => "transaction was already charged back",
+            RejectReason::UnexpectedAmount => "transactions referencing another transaction must not carry an amount",
+            RejectReason::NotADeposit => "referenced transaction is not a deposit",
+            RejectReason::ZeroAmount => "adjustment amount must not be zero",
+            RejectReason::ExceedsHeldAmount => "amount exceeds the funds held for the disputed transaction",
+            RejectReason::HeldCeilingExceeded => "dispute would exceed the client's held funds ceiling",
+            RejectReason::Overflow => "transaction would overflow a balance",
+            RejectReason::InsufficientHeldFunds => "account does not hold the disputed funds",
+            RejectReason::RejectedDeposit => "referenced deposit was rejected because the account was locked",
+            RejectReason::HeldExceedsNetDeposits => "held funds exceed net deposits",
+            RejectReason::TransactionLimitExceeded => "the limit on stored transactions was reached",
+            RejectReason::AmountAboveMaximum => "amount exceeds the configured maximum",
+            RejectReason::ChargedBackIdReused => "transaction ID belongs to a charged-back transaction",
+            RejectReason::OutOfSequence => "sequence number is below one already seen for the client",
+            RejectReason::DisputeLimitExceeded => "client reached the limit on disputes",
+            RejectReason::CurrencyMismatch => "currency differs from the account's currency",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for RejectReason {}
+
+/// Returns the available funds, held funds and pending fees of two accounts of the same client
+/// combined, or `None` if any sum overflows.
+fn merged_balances<A: Amount>(a: &Account<A>, b: &Account<A>) -> Option<(A, A, A)> {
+    Some((
+        a.available.checked_add(b.available)?,
+        a.held.checked_add(b.held)?,
+        a.pending_fees.checked_add(b.pending_fees)?,
+    ))
+}
+
+/// Returns why a resolve or chargeback of a transaction in the given state, which is not
+/// `Disputed`, is rejected.
+fn undisputed_reason(status: DisputeStatus) -> RejectReason {
+    match status {
+        DisputeStatus::NotDisputed => RejectReason::NotDisputed,
+        DisputeStatus::Resolved => RejectReason::AlreadyResolved,
+        DisputeStatus::ChargedBack => RejectReason::AlreadyChargedBack,
+        DisputeStatus::Rejected => RejectReason::RejectedDeposit,
+        DisputeStatus::Disputed | DisputeStatus::Reversed => RejectReason::InvalidDisputeStatus,
+    }
+}
+
+/// A manual administrative action taken on the engine, recorded for audit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAction {
+    /// A locked account was unlocked.
+    Unlock(ClientId),
+    /// A transaction's dispute status was overwritten without moving funds.
+    ForceDisputeStatus { tx_id: TransactionId, from: DisputeStatus, to: DisputeStatus },
+}
+
+/// Interest credited to a client's account by `accrue_interest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterestCredit<A = Decimal> {
+    /// The ID of the credited client.
+    pub client_id: ClientId,
+    /// The amount credited to the client's available funds.
+    pub amount: A,
+}
+
+/// An error returned when an account cannot be unlocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockError {
+    /// The client has no account.
+    UnknownClient(ClientId),
+    /// The client's account is not locked.
+    NotLocked(ClientId),
+}
+
+impl fmt::Display for UnlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnlockError::UnknownClient(client) => write!(f, "client {} has no account", client),
+            UnlockError::NotLocked(client) => write!(f, "account for client {} is not locked", client),
+        }
+    }
+}
+
+impl Error for UnlockError {}
+
+/// An error returned when a dispute status cannot be forced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStatusError {
+    /// No transaction with this ID is stored.
+    UnknownTransaction(TransactionId),
+}
+
+impl fmt::Display for DisputeStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisputeStatusError::UnknownTransaction(tx_id) => {
+                write!(f, "transaction {} is not stored", tx_id)
+            }
+        }
+    }
+}
+
+impl Error for DisputeStatusError {}
+
+/// An error returned when an opening balance cannot be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpeningBalanceError {
+    /// The client already has an account.
+    ExistingAccount(ClientId),
+    /// Adding the opening balance to the engine's net deposits, tracked for `held_check`,
+    /// would overflow.
+    Overflow(ClientId),
+}
+
+impl fmt::Display for OpeningBalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpeningBalanceError::ExistingAccount(client) => {
+                write!(f, "client {} already has an account", client)
+            }
+            OpeningBalanceError::Overflow(client) => {
+                write!(f, "opening balance for client {} would overflow the net deposits", client)
+            }
+        }
+    }
+}
+
+impl Error for OpeningBalanceError {}
+
+/// An error returned when two engines cannot be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// Both engines stored a transaction with this ID, so it is ambiguous which dispute state
+    /// applies.
+    DuplicateTransaction(TransactionId),
+    /// The client's accounts in both engines established different currencies.
+    CurrencyMismatch(ClientId),
+    /// Combining both engines would overflow a balance of the given client, or an engine-wide
+    /// total such as the collected fees if `None`.
+    Overflow(Option<ClientId>),
+    /// A buffered dispute, resolve or chargeback of this transaction, applied once both
+    /// engines were merged, was rejected with a reason that halts processing.
+    Rejected { tx_id: TransactionId, reason: RejectReason },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::DuplicateTransaction(tx_id) => {
+                write!(f, "transaction {} is stored in both engines", tx_id)
+            }
+            MergeError::Overflow(Some(client_id)) => {
+                write!(f, "combining the accounts for client {} would overflow a balance", client_id)
+            }
+            MergeError::Overflow(None) => f.write_str("combining the engines would overflow a total"),
+            MergeError::CurrencyMismatch(client_id) => {
+                write!(f, "accounts for client {} have different currencies", client_id)
+            }
+            MergeError::Rejected { tx_id, reason } => write!(f, "transaction {}: {}", tx_id, reason),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// Counts of applied and rejected transactions of a single type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeCounts {
+    /// Transactions that were applied successfully.
+    pub applied: u64,
+    /// Transactions that were rejected.
+    pub rejected: u64,
+}
+
+/// A histogram of amounts in powers-of-ten buckets: each bucket counts the amounts above the
+/// previous power of ten and up to its own, e.g. `(0.01, 1]` or `(1, 10]`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountHistogram {
+    /// Counts by the exponent of each bucket's upper bound.
+    counts: BTreeMap<i32, u64>,
+}
+
+#[cfg(feature = "metrics")]
+impl AmountHistogram {
+    /// Returns the non-empty buckets as `(bucket_upper_bound, count)`, smallest bound first.
+    /// Amounts above 10^28 fall in a final bucket bounded by `Decimal::MAX`.
+    pub fn buckets(&self) -> Vec<(Decimal, u64)> {
+        self.counts
+            .iter()
+            .map(|(&exponent, &count)| {
+                let bound = match u32::try_from(exponent) {
+                    Ok(exponent) => Decimal::try_from_i128_with_scale(10i128.pow(exponent), 0).unwrap_or(Decimal::MAX),
+                    Err(_) => Decimal::new(1, exponent.unsigned_abs()),
+                };
+                (bound, count)
+            })
+            .collect()
+    }
+
+    /// Counts a positive amount in its bucket.
+    fn record(&mut self, amount: Decimal) {
+        let mantissa = amount.mantissa().unsigned_abs();
+        let digits = mantissa.ilog10() as i32 + 1;
+        // An exact power of ten is the upper bound of its own bucket
+        let exponent = if mantissa == 10u128.pow(digits as u32 - 1) { digits - 1 } else { digits };
+        *self.counts.entry(exponent - amount.scale() as i32).or_default() += 1;
+    }
+
+    /// Adds another histogram's counts to these.
+    fn add(&mut self, other: &AmountHistogram) {
+        for (&exponent, &count) in &other.counts {
+            *self.counts.entry(exponent).or_default() += count;
+        }
+    }
+}
+
+/// Counts of the transactions applied by the engine, per transaction type, and of input rows
+/// that never became transactions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionCounts {
+    /// Rows skipped because they could not be read or deserialized into a transaction, other
+    /// than those counted in `unknown_type`.
+    pub malformed: u64,
+    /// Rows skipped because their `type` field names no known transaction type.
+    pub unknown_type: u64,
+    pub deposit: TypeCounts,
+    pub withdrawal: TypeCounts,
+    pub dispute: TypeCounts,
+    pub resolve: TypeCounts,
+    pub chargeback: TypeCounts,
+    pub reversal: TypeCounts,
+    pub adjustment: TypeCounts,
+    /// The amounts of applied deposits, before any fee.
+    #[cfg(feature = "metrics")]
+    pub deposit_amounts: AmountHistogram,
+    /// The amounts of applied withdrawals, before any fee.
+    #[cfg(feature = "metrics")]
+    pub withdrawal_amounts: AmountHistogram,
+}
+
+impl TransactionCounts {
+    /// Returns the counts for a transaction type.
+    pub fn get(&self, transaction_type: TransactionType) -> TypeCounts {
+        match transaction_type {
+            TransactionType::Deposit => self.deposit,
+            TransactionType::Withdrawal => self.withdrawal,
+            TransactionType::Dispute => self.dispute,
+            TransactionType::Resolve => self.resolve,
+            TransactionType::Chargeback => self.chargeback,
+            TransactionType::Reversal => self.reversal,
+            TransactionType::Adjustment => self.adjustment,
+        }
+    }
+
+    /// Returns the total number of input rows counted, whether applied, rejected or skipped.
+    pub fn total(&self) -> u64 {
+        let types = [
+            self.deposit,
+            self.withdrawal,
+            self.dispute,
+            self.resolve,
+            self.chargeback,
+            self.reversal,
+            self.adjustment,
+        ];
+        let transactions: u64 = types.iter().map(|counts| counts.applied + counts.rejected).sum();
+        self.malformed + self.unknown_type + transactions
+    }
+
+    /// Adds another set of counts to these.
+    fn add(&mut self, other: &TransactionCounts) {
+        self.malformed += other.malformed;
+        self.unknown_type += other.unknown_type;
+        for (counts, other) in [
+            (&mut self.deposit, other.deposit),
+            (&mut self.withdrawal, other.withdrawal),
+            (&mut self.dispute, other.dispute),
+            (&mut self.resolve, other.resolve),
+            (&mut self.chargeback, other.chargeback),
+            (&mut self.reversal, other.reversal),
+            (&mut self.adjustment, other.adjustment),
+        ] {
+            counts.applied += other.applied;
+            counts.rejected += other.rejected;
+        }
+        #[cfg(feature = "metrics")]
+        {
+            self.deposit_amounts.add(&other.deposit_amounts);
+            self.withdrawal_amounts.add(&other.withdrawal_amounts);
+        }
+    }
+
+    /// Records the outcome of applying a transaction of the given type.
+    fn record(&mut self, transaction_type: TransactionType, applied: bool) {
+        let counts = match transaction_type {
+            TransactionType::Deposit => &mut self.deposit,
+            TransactionType::Withdrawal => &mut self.withdrawal,
+            TransactionType::Dispute => &mut self.dispute,
+            TransactionType::Resolve => &mut self.resolve,
+            TransactionType::Chargeback => &mut self.chargeback,
+            TransactionType::Reversal => &mut self.reversal,
+            TransactionType::Adjustment => &mut self.adjustment,
+        };
+        if applied {
+            counts.applied += 1;
+        } else {
+            counts.rejected += 1;
+        }
+    }
+}
+
+/// A fee charged on deposits and withdrawals.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FeeSchedule {
+    /// A fixed fee charged per transaction.
+    pub flat: Decimal,
+    /// A fee charged as a fraction of the transaction amount, e.g. `0.01` for 1%.
+    pub rate: Decimal,
+}
+
+impl FeeSchedule {
+    /// Calculates the fee charged on a transaction of the given amount.
+    pub fn fee_for(&self, amount: Decimal) -> Decimal {
+        self.flat + amount * self.rate
+    }
+}
+
+/// How derived amounts, such as fees, are rounded to the configured scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round half to the nearest even digit ("banker's rounding").
+    #[default]
+    MidpointNearestEven,
+    /// Round half away from zero ("half-up").
+    MidpointAwayFromZero,
+    /// Round half towards zero.
+    MidpointTowardZero,
+    /// Always round towards zero, i.e. truncate.
+    ToZero,
+    /// Always round away from zero.
+    AwayFromZero,
+}
+
+impl RoundingMode {
+    /// Returns the equivalent `rust_decimal` rounding strategy.
+    pub fn strategy(self) -> RoundingStrategy {
+        match self {
+            RoundingMode::MidpointNearestEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::MidpointAwayFromZero => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::MidpointTowardZero => RoundingStrategy::MidpointTowardZero,
+            RoundingMode::ToZero => RoundingStrategy::ToZero,
+            RoundingMode::AwayFromZero => RoundingStrategy::AwayFromZero,
+        }
+    }
+
+    /// Rounds an amount to the given number of decimal places.
+    pub fn round(self, amount: Decimal, scale: u32) -> Decimal {
+        amount.round_dp_with_strategy(scale, self.strategy())
+    }
+}
+
+/// How a reversal is handled when the client has already spent the deposited funds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReversalPolicy {
+    /// Reject the reversal.
+    #[default]
+    RejectIfSpent,
+    /// Apply the reversal anyway, leaving the account with negative available funds.
+    AllowNegative,
+}
+
+/// How a deposit, withdrawal or adjustment is handled when its ID belongs to a stored
+/// transaction that was charged back, e.g. because IDs are recycled upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReusedIdPolicy {
+    /// Reject the transaction, keeping the charged-back record.
+    #[default]
+    RejectChargedBack,
+    /// Apply the transaction, replacing the charged-back record.
+    Overwrite,
+}
+
+/// Whether a chargeback freezes the client's account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChargebackPolicy {
+    /// Lock the account, so that it rejects further transactions.
+    #[default]
+    Lock,
+    /// Charge back the funds but leave the account usable, e.g. for low-value disputes.
+    KeepUnlocked,
+}
+
+/// What happens when the engine-wide held funds check configured by
+/// `EngineConfig::held_check` fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeldCheck {
+    /// Roll back the offending transaction and reject it as
+    /// `RejectReason::HeldExceedsNetDeposits`. Processing stops with an error naming it.
+    Error,
+    /// Panic, naming the offending transaction.
+    Panic,
+}
+
+/// Configuration for a `PaymentEngine`. The default configuration charges no fees and is
+/// lenient towards malformed rows.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// The fees charged on deposits and withdrawals, if any.
+    pub fee_schedule: Option<FeeSchedule>,
+    /// Reject disputes and reversals that carry an amount instead of ignoring it. Resolves and
+    /// chargebacks may always carry an amount, which makes them partial.
+    pub strict_amounts: bool,
+    /// How reversals of already spent deposits are handled.
+    pub reversal_policy: ReversalPolicy,
+    /// Whether chargebacks lock the account. They do by default.
+    pub chargeback_policy: ChargebackPolicy,
+    /// How transactions reusing the ID of a charged-back transaction are handled. By default
+    /// they are rejected, so that the charged-back record is never erased.
+    pub reused_id_policy: ReusedIdPolicy,
+    /// The number of decimal places fees and exported balances are rounded to. Full precision
+    /// is kept when unset.
+    pub scale: Option<u32>,
+    /// How amounts are rounded to `scale`.
+    pub rounding: RoundingMode,
+    /// Record an `AuditEntry` for every successfully applied transaction.
+    pub audit: bool,
+    /// Let disputes, resolves and chargebacks move held funds on a locked account. The
+    /// account stays locked either way.
+    pub allow_dispute_on_locked: bool,
+    /// Append a final `checksum` row to `export_accounts` holding the sum of every exported
+    /// account's total, for reconciliation.
+    pub checksum_row: bool,
+    /// The most a single client may have held under dispute at once. Disputes that would
+    /// exceed it are rejected.
+    pub max_held_per_client: Option<Decimal>,
+    /// The most deposits, withdrawals and adjustments kept for disputes. Beyond it the oldest
+    /// are evicted and disputes against them are rejected as unknown. Transactions under
+    /// dispute when evicted are kept and requeued as the newest, to be evicted once their
+    /// dispute has ended. All are kept when unset.
+    pub retention: Option<usize>,
+    /// How far below zero withdrawals may take a client's available funds. The limit is
+    /// measured from zero, so funds already negative because of a dispute count against it.
+    /// Withdrawals never go below zero when unset.
+    pub overdraft_limit: Option<Decimal>,
+    /// Reject withdrawals for clients without an account as `UnknownClient` instead of
+    /// creating an empty account for them, which would then appear in the export. Disputes
+    /// never create accounts.
+    pub reject_unknown_clients: bool,
+    /// Check after every transaction that the engine-wide held funds do not exceed the
+    /// engine-wide net deposits: everything credited to clients minus everything debited,
+    /// including chargebacks and reversals. A failure indicates an accounting bug, or disputes
+    /// and overdrafts that together took the engine's combined available funds below zero.
+    /// This is a development guardrail; it is off when unset.
+    pub held_check: Option<HeldCheck>,
+    /// Accrue fees on the account as `pending_fees` instead of deducting them from its
+    /// available funds. Deposits are then credited and withdrawals debited in full, and the
+    /// fees do not count towards `fees_collected`.
+    pub accrue_fees: bool,
+    /// How exported totals are computed. Set it to `TotalMode::NetOfPendingFees` to export
+    /// totals net of accrued fees.
+    pub total_mode: TotalMode,
+    /// The number of clients expected, used to pre-size the account map and avoid rehashing
+    /// as clients appear. It is only a hint: any number of clients is handled either way.
+    pub expected_clients: Option<usize>,
+    /// Buffer disputes, resolves and chargebacks that reference a transaction not seen yet,
+    /// instead of rejecting them, and retry them once a deposit, withdrawal or adjustment
+    /// with that ID is applied. Useful when input files may arrive out of order. Those
+    /// referencing a transaction evicted by `retention` are rejected as unknown instead.
+    pub buffer_orphan_disputes: bool,
+    /// Round exported totals by largest-remainder allocation instead of independently, so
+    /// that they sum exactly to the rounded grand total. Each account's available funds are
+    /// then its rounded total less its rounded held funds. Only applies when `scale` is set,
+    /// and only to full exports: `export_accounts`, `export_accounts_with` and
+    /// `accounts_to_json_value`.
+    pub preserve_sums: bool,
+    /// The most transactions stored for disputes. A deposit, withdrawal or adjustment that
+    /// would exceed it is rejected as `TransactionLimitExceeded`, which stops processing with
+    /// an error. Unlike `retention`, nothing is evicted; this is a hard limit guarding against
+    /// runaway or malicious input. There is no limit when unset.
+    pub max_transactions: Option<usize>,
+    /// The largest amount a single deposit or withdrawal may have. Larger ones, which usually
+    /// indicate corrupt input, are rejected. There is no maximum when unset.
+    pub max_amount: Option<Decimal>,
+    /// After each applied operation, debug-assert that the affected transaction's dispute
+    /// status followed a `valid_transition` from its previous one, and that a newly stored
+    /// transaction starts as `NotDisputed` or `Rejected`. Has no effect in release builds.
+    pub check_transitions: bool,
+    /// Reject transactions whose `seq` is below the highest one already seen for the same
+    /// client as `OutOfSequence`, guarding against replayed or duplicated messages when
+    /// several producers feed a client. Equal sequence numbers are accepted, and
+    /// transactions without one are never checked. Every transaction that passes the check
+    /// counts as seen, whether or not it is then applied.
+    pub enforce_seq_order: bool,
+    /// Which thousands separators CSV input amounts may contain. By default amounts must be
+    /// plain decimals, and rows with separators are malformed.
+    pub separators: SeparatorPolicy,
+    /// The most disputes applied per client over everything processed. Further disputes are
+    /// rejected as `DisputeLimitExceeded`, throttling dispute abuse. Disputes rejected for
+    /// any reason do not count. There is no limit when unset.
+    pub max_disputes_per_client: Option<u32>,
+}
+
+/// The state a single transaction may change, captured before applying it when `held_check`
+/// is set so that it can be rolled back if the check fails.
+struct Snapshot<A> {
+    account: Option<Account<A>>,
+    stored: Option<StoredTransaction<A>>,
+    last_seq: Option<u64>,
+    counts: TransactionCounts,
+    fees_collected: A,
+    held_total: A,
+    peak_held: A,
+}
+
+/// The main payment processing engine.
+///
+/// Balances are kept as `Decimal` by default; any other `Amount` type can be used instead via
+/// `with_amount_config`. Accounts and stored transactions are kept in memory in `HashMap`s by
+/// default; any other `Store` can be used instead via `with_stores`.
+pub struct PaymentEngine<
+    A = Decimal,
+    S = HashMap<ClientId, Account<A>>,
+    T = HashMap<TransactionId, StoredTransaction<A>>,
+> {
+    /// A map of client IDs to their accounts.
+    pub accounts: S,
+    /// A map of transaction IDs to their details, for dispute handling.
+    pub transactions: T,
+    config: EngineConfig,
+    fees_collected: A,
+    /// Clients whose balances or lock state changed since the last dirty export.
+    dirty: HashSet<ClientId>,
+    counts: TransactionCounts,
+    admin_actions: Vec<AdminAction>,
+    audit_log: Vec<AuditEntry>,
+    interest_credits: Vec<InterestCredit<A>>,
+    lock_hook: Option<LockHook<A>>,
+    /// Stored transaction IDs, oldest first, when a retention window is configured.
+    retained: VecDeque<TransactionId>,
+    evictions: u64,
+    /// IDs of the transactions evicted by the retention window.
+    evicted: HashSet<TransactionId>,
+    /// The sum of all accounts' held funds.
+    held_total: A,
+    peak_held: A,
+    /// The sum of all accounts' totals, maintained only when `held_check` is set.
+    net_deposits: A,
+    /// Orphan disputes, resolves and chargebacks by referenced transaction, in arrival order,
+    /// when `buffer_orphan_disputes` is set.
+    pending_disputes: BTreeMap<TransactionId, Vec<InputTransaction<A>>>,
+    /// The highest sequence number seen per client, when `enforce_seq_order` is set.
+    last_seq: HashMap<ClientId, u64>,
+}
+
+/// A callback invoked with the client's account whenever a chargeback locks it.
+type LockHook<A> = Box<dyn FnMut(ClientId, &Account<A>) + Send>;
+
+impl<A: Amount> Default for PaymentEngine<A> {
+    fn default() -> Self {
+        Self::with_amount_config(EngineConfig::default())
+    }
+}
+
+impl PaymentEngine {
+    /// Creates a new `PaymentEngine`.
+    pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    /// Creates a new `PaymentEngine` with the given configuration.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self::with_amount_config(config)
+    }
+
+    /// Rebuilds an engine purely from an audit log, using the default configuration. See
+    /// `replay_audit_with_config`.
+    pub fn replay_audit(entries: &[AuditEntry]) -> Result<Self, ReplayError> {
+        Self::replay_audit_with_config(entries, EngineConfig::default())
+    }
+
+    /// Rebuilds an engine purely from an audit log by reapplying each entry's transaction,
+    /// checking that the account's balances before and after it match the logged ones. Stops
+    /// at the first entry that is rejected or does not match.
+    ///
+    /// `config` should match the configuration the log was recorded with, since fees and
+    /// other settings change the balances. Auditing is always enabled on the rebuilt engine,
+    /// so its own log can be replayed in turn.
+    ///
+    /// Only engines driven purely by `apply` and the processing functions built on it can be
+    /// rebuilt. The log does not record `unlock_account`, `force_dispute_status`,
+    /// `set_opening_balance`, `accrue_interest` or `sweep_dust`. Replaying the log of an
+    /// engine that used them fails at the next entry for an affected client, or, if there is
+    /// none, rebuilds the accounts without their changes.
+    pub fn replay_audit_with_config(entries: &[AuditEntry], config: EngineConfig) -> Result<Self, ReplayError> {
+        let mut engine = Self::with_config(EngineConfig { audit: true, ..config });
+        for (index, entry) in entries.iter().enumerate() {
+            let error = |kind| ReplayError { index, tx_id: entry.tx_id, kind };
+            let replayed = engine.balances(entry.client_id);
+            let logged = (entry.available_before, entry.held_before);
+            if replayed != logged {
+                return Err(error(ReplayErrorKind::BalanceBeforeMismatch { logged, replayed }));
+            }
+
+            engine
+                .apply(InputTransaction {
+                    transaction_type: entry.transaction_type,
+                    client_id: entry.client_id,
+                    tx_id: entry.tx_id,
+                    amount: entry.amount,
+                    currency: None,
+                    reference: None,
+                    seq: None,
+                })
+                .map_err(|reason| error(ReplayErrorKind::Rejected(reason)))?;
+
+            let replayed = engine.balances(entry.client_id);
+            let logged = (entry.available_after, entry.held_after);
+            if replayed != logged {
+                return Err(error(ReplayErrorKind::BalanceAfterMismatch { logged, replayed }));
+            }
+        }
+        Ok(engine)
+    }
+}
+
+impl<A: Amount> PaymentEngine<A> {
+    /// Creates a new `PaymentEngine` keeping balances in the amount type `A`, with the given
+    /// configuration.
+    pub fn with_amount_config(config: EngineConfig) -> Self {
+        let accounts = HashMap::with_capacity(config.expected_clients.unwrap_or(0));
+        Self::with_stores(config, accounts, HashMap::new())
+    }
+
+    /// Creates a new `PaymentEngine` with the default configuration and applies each
+    /// transaction in order. Rejected transactions are counted and otherwise skipped, as
+    /// with `apply`.
+    pub fn from_transactions<I>(transactions: I) -> Self
+    where
+        I: IntoIterator<Item = InputTransaction<A>>,
+    {
+        let mut engine = Self::default();
+        for tx in transactions {
+            let _ = engine.apply(tx);
+        }
+        engine
+    }
+}
+
+impl<A, S, T> PaymentEngine<A, S, T>
+where
+    A: Amount,
+    S: Store<ClientId, Account<A>>,
+    T: Store<TransactionId, StoredTransaction<A>>,
+{
+    /// Creates a new `PaymentEngine` with the given configuration that keeps accounts and
+    /// stored transactions in the given stores, e.g. on-disk ones for inputs that do not fit
+    /// in memory. The stores should be empty: the engine's running totals start at zero.
+    /// `EngineConfig::expected_clients` is not applied to them.
+    pub fn with_stores(config: EngineConfig, accounts: S, transactions: T) -> Self {
+        Self {
+            accounts,
+            transactions,
+            config,
+            fees_collected: A::ZERO,
+            dirty: HashSet::new(),
+            counts: TransactionCounts::default(),
+            admin_actions: Vec::new(),
+            pending_disputes: BTreeMap::new(),
+            last_seq: HashMap::new(),
+            audit_log: Vec::new(),
+            interest_credits: Vec::new(),
+            lock_hook: None,
+            retained: VecDeque::new(),
+            evictions: 0,
+            evicted: HashSet::new(),
+            held_total: A::ZERO,
+            peak_held: A::ZERO,
+            net_deposits: A::ZERO,
+        }
+    }
+
+    /// Registers a hook that is called right after a chargeback locks a client's account,
+    /// replacing any previous hook. It is called once per applied chargeback under
+    /// `ChargebackPolicy::Lock`, including on accounts that were already locked, and only
+    /// sees the account read-only. The hook must be `Send` so that the engine can be moved
+    /// between threads.
+    pub fn on_lock<F>(&mut self, hook: F)
+    where
+        F: FnMut(ClientId, &Account<A>) + Send + 'static,
+    {
+        self.lock_hook = Some(Box::new(hook));
+    }
+
+    /// Returns the engine's configuration.
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns the total fees collected on all deposits and withdrawals.
+    pub fn fees_collected(&self) -> A {
+        self.fees_collected
+    }
+
+    /// Returns the number of transactions applied and rejected so far, per type.
+    /// Only transactions passed through `apply` (including via `process_transactions`) are
+    /// counted.
+    pub fn counts(&self) -> &TransactionCounts {
+        &self.counts
+    }
+
+    /// Calculates the fee for a deposit or withdrawal of the given amount.
+    fn fee_for(&self, amount: A) -> A {
+        let fee = self
+            .config
+            .fee_schedule
+            .map_or(Decimal::ZERO, |schedule| schedule.fee_for(amount.to_decimal()));
+        A::from_decimal(self.round(fee))
+    }
+
+    /// Rounds a derived amount to the configured scale, if any.
+    fn round(&self, amount: Decimal) -> Decimal {
+        match self.config.scale {
+            Some(scale) => self.config.rounding.round(amount, scale),
+            None => amount,
+        }
+    }
+
+    /// Converts an account to its output representation, rounded to the configured scale.
+    fn output_account(&self, account: &Account<A>) -> Result<OutputAccount, ExportError> {
+        let mut output = OutputAccount::with_total_mode(account, self.config.total_mode)?;
+        output.available = self.round(output.available);
+        output.held = self.round(output.held);
+        output.total = self.round(output.total);
+        Ok(output)
+    }
+
+    /// Converts accounts to their output representation like `output_account`, but with
+    /// sum-preserving rounding of the totals if configured.
+    fn output_accounts(&self, accounts: &[&Account<A>]) -> Result<Vec<OutputAccount>, ExportError> {
+        let (Some(scale), true) = (self.config.scale, self.config.preserve_sums) else {
+            return accounts.iter().map(|account| self.output_account(account)).collect();
+        };
+        let mut outputs = accounts
+            .iter()
+            .map(|account| OutputAccount::with_total_mode(account, self.config.total_mode))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Floor every total, then hand the units lost to flooring back to the accounts with
+        // the largest remainders, lowest client ID first on ties
+        let unit = Decimal::new(1, scale);
+        let grand_total = self.round(outputs.iter().map(|output| output.total).sum());
+        let floored: Vec<_> = outputs
+            .iter()
+            .map(|output| output.total.round_dp_with_strategy(scale, RoundingStrategy::ToNegativeInfinity))
+            .collect();
+        let mut remaining = grand_total - floored.iter().copied().sum::<Decimal>();
+        let mut by_remainder: Vec<_> = (0..outputs.len()).collect();
+        by_remainder.sort_by(|&a, &b| (outputs[b].total - floored[b]).cmp(&(outputs[a].total - floored[a])));
+
+        for (index, output) in outputs.iter_mut().enumerate() {
+            output.total = floored[index];
+        }
+        for index in by_remainder {
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+            outputs[index].total += unit;
+            remaining -= unit;
+        }
+        for output in &mut outputs {
+            output.held = self.round(output.held);
+            output.available = output.total - output.held;
+        }
+        Ok(outputs)
+    }
+
+    /// Processes all transactions from a given reader and updates account states.
+    ///
+    /// Transactions are expected to be in CSV format, with columns matched by header name so
+    /// they may appear in any order. Invalid transactions are ignored and malformed rows are
+    /// skipped and counted in `counts`, with rows of an unknown type counted separately, but a
+    /// missing required column, a failed `held_check` or reaching `max_transactions` is an
+    /// error.
+    pub fn process_transactions<R: io::Read>(&mut self, reader: R) -> Result<(), AccountantError> {
+        self.process_rows(reader, usize::MAX)
+    }
+
+    /// Processes transactions like `process_transactions`, but stops cleanly after the first
+    /// `limit` rows, e.g. to sample a large file. Every row counts towards the limit, including
+    /// malformed ones, so the result matches processing a copy truncated to `limit` rows.
+    pub fn process_transactions_limited<R: io::Read>(
+        &mut self,
+        reader: R,
+        limit: usize,
+    ) -> Result<(), AccountantError> {
+        self.process_rows(reader, limit)
+    }
+
+    /// Processes CSV rows, stopping after `limit` rows.
+    fn process_rows<R: io::Read>(&mut self, reader: R, limit: usize) -> Result<(), AccountantError> {
+        let mut rdr = csv_reader(reader);
+        let columns = ColumnIndices::from_headers(rdr.headers()?).map_err(AccountantError::MissingColumn)?;
+
+        let mut record = csv::StringRecord::new();
+        let mut rows = 0;
+        while rows < limit {
+            rows += 1;
+            match rdr.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(_) => {
+                    self.counts.malformed += 1;
+                    continue;
+                }
+            }
+            match columns.read::<A>(&record, self.config.separators) {
+                Ok(tx) => {
+                    let tx_id = tx.tx_id;
+                    match self.apply(tx) {
+                        Err(reason) if reason.halts_processing() => {
+                            return Err(AccountantError::Rejected { tx_id, reason });
+                        }
+                        _ => {}
+                    }
+                }
+                Err(RowError::UnknownType(_)) => self.counts.unknown_type += 1,
+                Err(RowError::Malformed(_)) => self.counts.malformed += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies transactions from an async stream as they arrive, e.g. from a message queue,
+    /// without blocking the runtime between them. Processing itself stays synchronous.
+    ///
+    /// As with `process_transactions`, invalid transactions are ignored, but a failed
+    /// `held_check` or reaching `max_transactions` stops with an error. Only available with
+    /// the `async` feature.
+    #[cfg(feature = "async")]
+    pub async fn process_stream<St>(&mut self, stream: St) -> Result<(), AccountantError>
+    where
+        St: futures::Stream<Item = InputTransaction<A>>,
+    {
+        use futures::StreamExt;
+
+        futures::pin_mut!(stream);
+        while let Some(tx) = stream.next().await {
+            let tx_id = tx.tx_id;
+            match self.apply(tx) {
+                Err(reason) if reason.halts_processing() => {
+                    return Err(AccountantError::Rejected { tx_id, reason });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes transactions encoded in the fixed-width binary format described in
+    /// `binary::RECORD_LEN`, bypassing CSV parsing.
+    ///
+    /// As with `process_transactions`, invalid transactions are ignored and records with an
+    /// unknown type code are counted in `counts`. A truncated final record is counted as
+    /// malformed.
+    pub fn process_transactions_binary<R: io::Read>(&mut self, reader: R) -> Result<(), AccountantError> {
+        let mut reader = io::BufReader::new(reader);
+        let mut record = [0; binary::RECORD_LEN];
+        loop {
+            match binary::read_record(&mut reader, &mut record)? {
+                0 => return Ok(()),
+                binary::RECORD_LEN => {}
+                _ => {
+                    self.counts.malformed += 1;
+                    return Ok(());
+                }
+            }
+            match binary::decode(&record) {
+                Decoded::Transaction(tx) => {
+                    let tx_id = tx.tx_id;
+                    match self.apply(tx) {
+                        Err(reason) if reason.halts_processing() => {
+                            return Err(AccountantError::Rejected { tx_id, reason });
+                        }
+                        _ => {}
+                    }
+                }
+                Decoded::UnknownType(_) => self.counts.unknown_type += 1,
+            }
+        }
+    }
+
+    /// Processes all transactions from a given reader, stopping at the first malformed row or
+    /// rejected transaction.
+    ///
+    /// Unlike `process_transactions`, nothing is ignored: the returned error identifies the
+    /// offending line. Transactions before that line remain applied.
+    pub fn process_transactions_strict<R: io::Read>(&mut self, reader: R) -> Result<(), ProcessError> {
+        let mut rdr = csv_reader(reader);
+        let headers = rdr
+            .headers()
+            .map_err(|err| ProcessError { line: 1, kind: ProcessErrorKind::Csv(err) })?;
+        let columns = ColumnIndices::from_headers(headers)
+            .map_err(|column| ProcessError { line: 1, kind: ProcessErrorKind::MissingColumn(column) })?;
+
+        let mut record = csv::StringRecord::new();
+        loop {
+            match rdr.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    let line = err.position().map_or(0, |pos| pos.line());
+                    return Err(ProcessError { line, kind: ProcessErrorKind::Csv(err) });
+                }
+            }
+            let line = record.position().map_or(0, |pos| pos.line());
+            let tx = columns.read::<A>(&record, self.config.separators).map_err(|err| {
+                let kind = match err {
+                    RowError::UnknownType(value) => ProcessErrorKind::UnknownType(value),
+                    RowError::Malformed(field) => ProcessErrorKind::InvalidField(field),
+                };
+                ProcessError { line, kind }
+            })?;
+            self.apply(tx)
+                .map_err(|reason| ProcessError { line, kind: ProcessErrorKind::Rejected(reason) })?;
+        }
+        Ok(())
+    }
+
+    /// Applies a single transaction, dispatching it to the handler for its type and counting
+    /// the outcome. When auditing is enabled, successful transactions are also recorded in the
+    /// audit log.
+    ///
+    /// With `buffer_orphan_disputes`, a dispute, resolve or chargeback referencing an unknown
+    /// transaction is buffered and `Ok` is returned without counting it. It is applied and
+    /// counted once a transaction with that ID is, and stays in `pending_disputes` until then.
+    /// Its sequence number, if any, is checked on arrival rather than when it is applied. One
+    /// referencing a transaction evicted by the retention window is rejected, not buffered.
+    pub fn apply(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        let transaction_type = tx.transaction_type;
+        let tx_id = tx.tx_id;
+        let orphan = (self.config.buffer_orphan_disputes
+            && matches!(
+                transaction_type,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            )
+            && !self.evicted.contains(&tx_id))
+        .then(|| tx.clone());
+        let client_id = tx.client_id;
+        let reference = tx.reference.clone();
+        let seq = tx.seq.filter(|_| self.config.enforce_seq_order);
+        let prior_status = self
+            .config
+            .check_transitions
+            .then(|| self.transactions.get(&tx_id).map(|t| t.dispute_status));
+        let audit = self.config.audit.then(|| {
+            let amount = tx.amount.map(A::to_decimal);
+            (tx.tx_id, tx.client_id, amount, self.balances(tx.client_id))
+        });
+        let snapshot = self.config.held_check.map(|check| (check, self.snapshot(client_id, tx_id)));
+        let result = match seq {
+            Some(seq) if self.last_seq.get(&client_id).is_some_and(|&last| seq < last) => {
+                Err(RejectReason::OutOfSequence)
+            }
+            Some(seq) => {
+                self.last_seq.insert(client_id, seq);
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        let result = result.and_then(|()| match transaction_type {
+            TransactionType::Deposit => self.handle_deposit(tx),
+            TransactionType::Withdrawal => self.handle_withdrawal(tx),
+            TransactionType::Dispute => self.handle_dispute(tx),
+            TransactionType::Resolve => self.handle_resolve(tx),
+            TransactionType::Chargeback => self.handle_chargeback(tx),
+            TransactionType::Reversal => self.handle_reversal(tx),
+            TransactionType::Adjustment => self.handle_adjustment(tx),
+        });
+        let result = match (result, snapshot) {
+            (Ok(()), Some((check, snapshot))) => self.check_held(check, client_id, tx_id, snapshot),
+            (result, _) => result,
+        };
+        if let Some(prior) = prior_status {
+            self.check_transition(tx_id, prior);
+        }
+        if let (Some(orphan), Err(RejectReason::UnknownTransaction)) = (orphan, &result) {
+            self.pending_disputes.entry(tx_id).or_default().push(orphan);
+            return Ok(());
+        }
+        self.counts.record(transaction_type, result.is_ok());
+
+        if let (Some((tx_id, client_id, amount, (available_before, held_before))), Ok(())) = (audit, &result) {
+            let (available_after, held_after) = self.balances(client_id);
+            self.audit_log.push(AuditEntry {
+                tx_id,
+                client_id,
+                transaction_type,
+                amount,
+                available_before,
+                held_before,
+                available_after,
+                held_after,
+            });
+        }
+        if let (Some(reference), Ok(())) = (reference, &result) {
+            if let Some(account) = self.accounts.get_mut(&client_id) {
+                account.last_reference = Some(reference);
+            }
+        }
+        result?;
+        self.apply_pending(tx_id)
+    }
+
+    /// Applies the disputes, resolves and chargebacks buffered for `tx_id` now that it is
+    /// known. Stops at the first one rejected with a reason that halts processing and returns
+    /// that reason, buffering the rest again.
+    fn apply_pending(&mut self, tx_id: TransactionId) -> Result<(), RejectReason> {
+        let mut orphans = self.pending_disputes.remove(&tx_id).unwrap_or_default().into_iter();
+        while let Some(mut orphan) = orphans.next() {
+            orphan.seq = None;
+            match self.apply(orphan) {
+                Err(reason) if reason.halts_processing() => {
+                    let rest: Vec<_> = orphans.collect();
+                    if !rest.is_empty() {
+                        self.pending_disputes.entry(tx_id).or_default().extend(rest);
+                    }
+                    return Err(reason);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Debug-asserts that the status of `tx_id` could have been reached from `prior`, its
+    /// status before the last operation, or `None` if it was not stored then.
+    fn check_transition(&self, tx_id: TransactionId, prior: Option<DisputeStatus>) {
+        let Some(current) = self.transactions.get(&tx_id).map(|t| t.dispute_status) else {
+            return;
+        };
+        match prior {
+            Some(prior) => debug_assert!(
+                valid_transition(prior, current),
+                "transaction {} moved from {:?} to {:?}",
+                tx_id,
+                prior,
+                current
+            ),
+            None => debug_assert!(
+                matches!(current, DisputeStatus::NotDisputed | DisputeStatus::Rejected),
+                "transaction {} was stored as {:?}",
+                tx_id,
+                current
+            ),
+        }
+    }
+
+    /// Returns the buffered disputes, resolves and chargebacks whose referenced transaction
+    /// has not been seen, ordered by that transaction's ID and then by arrival. Always empty
+    /// unless `EngineConfig::buffer_orphan_disputes` is set.
+    pub fn pending_disputes(&self) -> impl Iterator<Item = &InputTransaction<A>> {
+        self.pending_disputes.values().flatten()
+    }
+
+    /// Applies a single transaction like `apply` and returns the outcome together with a
+    /// snapshot of the client's account afterwards, or `None` if the client has no account.
+    pub fn apply_one(
+        &mut self,
+        tx: InputTransaction<A>,
+    ) -> (Result<(), RejectReason>, Option<AccountSnapshot<A>>) {
+        let client_id = tx.client_id;
+        let result = self.apply(tx);
+        let snapshot = self.accounts.get(&client_id).map(Account::snapshot);
+        (result, snapshot)
+    }
+
+    /// Returns a client's available and held funds, which are zero if it has no account.
+    fn balances(&self, client: ClientId) -> (Decimal, Decimal) {
+        self.accounts.get(&client).map_or((Decimal::ZERO, Decimal::ZERO), |a| {
+            (a.available.to_decimal(), a.held.to_decimal())
+        })
+    }
+
+    /// Handles a deposit transaction.
+    /// Increases the client's available funds by the amount less any fee, or accrues the fee
+    /// if configured, and records the transaction.
+    /// Rejects deposits to locked accounts, with non-positive amounts or amounts above any
+    /// configured maximum, that do not cover the fee, or that would overflow the balance.
+    /// When auditing, deposits rejected because the account is locked are still stored,
+    /// marked `Rejected`, unless their ID is taken.
+    pub fn handle_deposit(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        self.check_storable(tx.tx_id)?;
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        if amount <= A::ZERO {
+            return Err(RejectReason::NonPositiveAmount);
+        }
+        self.check_max_amount(amount)?;
+        let fee = self.fee_for(amount);
+        if amount - fee <= A::ZERO {
+            return Err(RejectReason::AmountBelowFee);
+        }
+        let credited = if self.config.accrue_fees { amount } else { amount - fee };
+
+        let account = self
+            .accounts
+            .get_or_insert_with(tx.client_id, || Account::new(tx.client_id));
+        if account.locked {
+            if self.config.audit && !self.transactions.contains_key(&tx.tx_id) {
+                self.store(
+                    tx.tx_id,
+                    StoredTransaction {
+                        client_id: tx.client_id,
+                        transaction_type: TransactionType::Deposit,
+                        amount: credited,
+                        fee,
+                        held: A::ZERO,
+                        dispute_status: DisputeStatus::Rejected,
+                    },
+                );
+            }
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+
+        let available = account.available.checked_add(credited).ok_or(RejectReason::Overflow)?;
+        let (pending_fees, fees_collected) = if self.config.accrue_fees {
+            (account.pending_fees.checked_add(fee).ok_or(RejectReason::Overflow)?, self.fees_collected)
+        } else {
+            (account.pending_fees, self.fees_collected.checked_add(fee).ok_or(RejectReason::Overflow)?)
+        };
+
+        account.available = available;
+        account.pending_fees = pending_fees;
+        if account.currency.is_none() {
+            account.currency = tx.currency;
+        }
+        self.fees_collected = fees_collected;
+        self.store(
+            tx.tx_id,
+            StoredTransaction {
+                client_id: tx.client_id,
+                transaction_type: TransactionType::Deposit,
+                amount: credited,
+                fee,
+                held: A::ZERO,
+                dispute_status: DisputeStatus::NotDisputed,
+            },
+        );
+        self.dirty.insert(tx.client_id);
+        #[cfg(feature = "metrics")]
+        self.counts.deposit_amounts.record(amount.to_decimal());
+        Ok(())
+    }
+
+    /// Handles a withdrawal transaction.
+    /// Decreases the client's available funds by the amount plus any fee, or accrues the fee
+    /// if configured, if sufficient funds are available, counting any configured overdraft
+    /// limit.
+    /// Rejects withdrawals from locked accounts, with non-positive amounts or amounts above any
+    /// configured maximum, and, if configured, from clients without an account.
+    pub fn handle_withdrawal(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        self.check_storable(tx.tx_id)?;
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        if amount <= A::ZERO {
+            return Err(RejectReason::NonPositiveAmount);
+        }
+        self.check_max_amount(amount)?;
+        let fee = self.fee_for(amount);
+        let debited = if self.config.accrue_fees {
+            amount
+        } else {
+            amount.checked_add(fee).ok_or(RejectReason::Overflow)?
+        };
+        let floor = self.config.overdraft_limit.map_or(A::ZERO, |limit| -A::from_decimal(limit));
+
+        let account = if self.config.reject_unknown_clients {
+            self.accounts.get_mut(&tx.client_id).ok_or(RejectReason::UnknownClient)?
+        } else {
+            self.accounts.get_or_insert_with(tx.client_id, || Account::new(tx.client_id))
+        };
+        if account.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        let available = account.available.checked_sub(debited).ok_or(RejectReason::Overflow)?;
+        if available < floor {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        let (pending_fees, fees_collected) = if self.config.accrue_fees {
+            (account.pending_fees.checked_add(fee).ok_or(RejectReason::Overflow)?, self.fees_collected)
+        } else {
+            (account.pending_fees, self.fees_collected.checked_add(fee).ok_or(RejectReason::Overflow)?)
+        };
+
+        account.available = available;
+        account.pending_fees = pending_fees;
+        self.fees_collected = fees_collected;
+        self.store(
+            tx.tx_id,
+            StoredTransaction {
+                client_id: tx.client_id,
+                transaction_type: TransactionType::Withdrawal,
+                amount,
+                fee,
+                held: A::ZERO,
+                dispute_status: DisputeStatus::NotDisputed,
+            },
+        );
+        self.dirty.insert(tx.client_id);
+        #[cfg(feature = "metrics")]
+        self.counts.withdrawal_amounts.record(amount.to_decimal());
+        Ok(())
+    }
+
+    /// Handles an adjustment transaction.
+    /// Applies a signed correction to the client's available funds and records the
+    /// transaction. No fee is charged.
+    /// Rejects adjustments to locked accounts or with a zero amount, and negative adjustments
+    /// that exceed the client's available funds.
+    pub fn handle_adjustment(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        self.check_storable(tx.tx_id)?;
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        if amount == A::ZERO {
+            return Err(RejectReason::ZeroAmount);
+        }
+
+        let account = self
+            .accounts
+            .get_or_insert_with(tx.client_id, || Account::new(tx.client_id));
+        if account.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        let available = account.available.checked_add(amount).ok_or(RejectReason::Overflow)?;
+        if available < A::ZERO {
+            return Err(RejectReason::InsufficientFunds);
+        }
+
+        account.available = available;
+        self.store(
+            tx.tx_id,
+            StoredTransaction {
+                client_id: tx.client_id,
+                transaction_type: TransactionType::Adjustment,
+                amount,
+                fee: A::ZERO,
+                held: A::ZERO,
+                dispute_status: DisputeStatus::NotDisputed,
+            },
+        );
+        self.dirty.insert(tx.client_id);
+        Ok(())
+    }
+
+    /// Checks a deposit or withdrawal amount against `max_amount`, if configured.
+    fn check_max_amount(&self, amount: A) -> Result<(), RejectReason> {
+        match self.config.max_amount {
+            Some(max) if amount.to_decimal() > max => Err(RejectReason::AmountAboveMaximum),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that a transaction with the given ID may be stored. Replacing a charged-back
+    /// transaction is rejected unless `reused_id_policy` allows it. Otherwise the stored
+    /// transactions must stay within `max_transactions`, if configured, though replacing an
+    /// already stored transaction is always allowed.
+    fn check_storable(&self, tx_id: TransactionId) -> Result<(), RejectReason> {
+        if self.config.reused_id_policy == ReusedIdPolicy::RejectChargedBack
+            && self
+                .transactions
+                .get(&tx_id)
+                .is_some_and(|stored| stored.dispute_status == DisputeStatus::ChargedBack)
+        {
+            return Err(RejectReason::ChargedBackIdReused);
+        }
+        match self.config.max_transactions {
+            Some(max) if self.transactions.len() >= max && !self.transactions.contains_key(&tx_id) => {
+                Err(RejectReason::TransactionLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Captures the state a transaction for `client` referencing `tx_id` may change, so that
+    /// it can be rolled back if it fails the held funds check.
+    fn snapshot(&self, client: ClientId, tx_id: TransactionId) -> Snapshot<A> {
+        Snapshot {
+            account: self.accounts.get(&client).cloned(),
+            stored: self.transactions.get(&tx_id).cloned(),
+            last_seq: self.last_seq.get(&client).copied(),
+            counts: self.counts.clone(),
+            fees_collected: self.fees_collected,
+            held_total: self.held_total,
+            peak_held: self.peak_held,
+        }
+    }
+
+    /// Updates the net deposits by the change in the client's total since `snapshot` and
+    /// checks the engine-wide held funds against them. On failure the transaction is rolled
+    /// back to `snapshot`, though a lock hook it triggered has already run and stored
+    /// transactions it evicted stay evicted.
+    fn check_held(
+        &mut self,
+        check: HeldCheck,
+        client: ClientId,
+        tx_id: TransactionId,
+        snapshot: Snapshot<A>,
+    ) -> Result<(), RejectReason> {
+        let total = |account: Option<&Account<A>>| account.map_or(Some(A::ZERO), Account::checked_total);
+        let net_deposits = total(self.accounts.get(&client))
+            .zip(total(snapshot.account.as_ref()))
+            .and_then(|(after, before)| self.net_deposits.checked_add(after)?.checked_sub(before));
+        let reason = match net_deposits {
+            Some(net_deposits) if self.held_total <= net_deposits => {
+                self.net_deposits = net_deposits;
+                return Ok(());
+            }
+            Some(net_deposits) => match check {
+                HeldCheck::Error => RejectReason::HeldExceedsNetDeposits,
+                HeldCheck::Panic => panic!(
+                    "transaction {}: held funds {:?} exceed net deposits {:?}",
+                    tx_id, self.held_total, net_deposits
+                ),
+            },
+            None => RejectReason::Overflow,
+        };
+        self.restore(client, tx_id, snapshot);
+        Err(reason)
+    }
+
+    /// Rolls back a transaction for `client` referencing `tx_id` to the state captured in
+    /// `snapshot`.
+    fn restore(&mut self, client: ClientId, tx_id: TransactionId, snapshot: Snapshot<A>) {
+        match snapshot.account {
+            Some(account) => self.accounts.insert(client, account),
+            None => self.accounts.remove(&client),
+        };
+        match snapshot.stored {
+            Some(stored) => self.transactions.insert(tx_id, stored),
+            None => {
+                if self.retained.back() == Some(&tx_id) {
+                    self.retained.pop_back();
+                }
+                self.transactions.remove(&tx_id)
+            }
+        };
+        match snapshot.last_seq {
+            Some(seq) => self.last_seq.insert(client, seq),
+            None => self.last_seq.remove(&client),
+        };
+        self.counts = snapshot.counts;
+        self.fees_collected = snapshot.fees_collected;
+        self.held_total = snapshot.held_total;
+        self.peak_held = snapshot.peak_held;
+    }
+
+    /// Stores a transaction for potential disputes, evicting the oldest stored transactions
+    /// beyond the configured retention window.
+    fn store(&mut self, tx_id: TransactionId, stored: StoredTransaction<A>) {
+        self.transactions.insert(tx_id, stored);
+        let Some(retention) = self.config.retention else {
+            return;
+        };
+        self.retained.push_back(tx_id);
+        // Requeued disputed transactions are scanned at most once, in case all are disputed
+        let mut requeued = 0;
+        while self.retained.len() > retention && requeued < self.retained.len() {
+            let Some(oldest) = self.retained.pop_front() else {
+                break;
+            };
+            let disputed = self
+                .transactions
+                .get(&oldest)
+                .is_some_and(|t| t.dispute_status == DisputeStatus::Disputed);
+            if disputed {
+                self.retained.push_back(oldest);
+                requeued += 1;
+            } else if self.transactions.remove(&oldest).is_some() {
+                self.evictions += 1;
+                self.evicted.insert(oldest);
+            }
+        }
+    }
+
+    /// Returns the number of stored transactions evicted by the retention window.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Handles a dispute transaction.
+    /// Moves funds from available to held for the disputed transaction. Disputing a debit
+    /// instead holds the debited amount on top of available funds, provisionally returning it
+    /// to the client.
+    /// The referenced transaction must exist and not be currently disputed, charged back or
+    /// reversed, the client's held funds must stay within any configured ceiling, and the
+    /// client must not have reached any configured dispute limit.
+    pub fn handle_dispute(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        self.check_no_amount(&tx)?;
+        let disputed_tx = self
+            .transactions
+            .get_mut(&tx.tx_id)
+            .ok_or(RejectReason::UnknownTransaction)?;
+        if disputed_tx.client_id != tx.client_id {
+            return Err(RejectReason::ClientMismatch);
+        }
+        if disputed_tx.dispute_status == DisputeStatus::Rejected {
+            return Err(RejectReason::RejectedDeposit);
+        }
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        if account.locked && !self.config.allow_dispute_on_locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        match disputed_tx.dispute_status {
+            DisputeStatus::NotDisputed | DisputeStatus::Resolved => {}
+            DisputeStatus::Disputed => return Err(RejectReason::AlreadyDisputed),
+            _ => return Err(RejectReason::InvalidDisputeStatus),
+        }
+        if self.config.max_disputes_per_client.is_some_and(|max| account.disputes >= max) {
+            return Err(RejectReason::DisputeLimitExceeded);
+        }
+
+        let amount = disputed_tx.disputed_amount();
+        let available = if disputed_tx.is_debit() {
+            account.available
+        } else {
+            account.available.checked_sub(amount).ok_or(RejectReason::Overflow)?
+        };
+        let held = account.held.checked_add(amount).ok_or(RejectReason::Overflow)?;
+        if let Some(ceiling) = self.config.max_held_per_client {
+            if held.to_decimal() > ceiling {
+                return Err(RejectReason::HeldCeilingExceeded);
+            }
+        }
+        let held_total = self.held_total.checked_add(amount).ok_or(RejectReason::Overflow)?;
+
+        account.available = available;
+        account.held = held;
+        account.disputes += 1;
+        disputed_tx.held = amount;
+        disputed_tx.dispute_status = DisputeStatus::Disputed;
+        self.held_total = held_total;
+        if held_total > self.peak_held {
+            self.peak_held = held_total;
+        }
+        self.dirty.insert(tx.client_id);
+        Ok(())
+    }
+
+    /// Handles a resolve transaction.
+    /// Moves funds from held back to available, resolving the dispute. If the row carries an
+    /// amount, only that much is released: the rest stays held and the transaction remains
+    /// under dispute until it is fully resolved. For a debit the released funds are dropped
+    /// instead, since resolving upholds the debit.
+    /// The referenced transaction must exist and be under dispute.
+    pub fn handle_resolve(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        let disputed_tx = self
+            .transactions
+            .get_mut(&tx.tx_id)
+            .ok_or(RejectReason::UnknownTransaction)?;
+        if disputed_tx.client_id != tx.client_id {
+            return Err(RejectReason::ClientMismatch);
+        }
+        if disputed_tx.dispute_status != DisputeStatus::Disputed {
+            return Err(undisputed_reason(disputed_tx.dispute_status));
+        }
+        let released = match tx.amount {
+            Some(amount) if amount <= A::ZERO => return Err(RejectReason::NonPositiveAmount),
+            Some(amount) if amount > disputed_tx.held => return Err(RejectReason::ExceedsHeldAmount),
+            Some(amount) => amount,
+            None => disputed_tx.held,
+        };
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        if account.locked && !self.config.allow_dispute_on_locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        if account.held < released {
+            return Err(RejectReason::InsufficientHeldFunds);
+        }
+        let available = if disputed_tx.is_debit() {
+            account.available
+        } else {
+            account.available.checked_add(released).ok_or(RejectReason::Overflow)?
+        };
+        let held = account.held.checked_sub(released).ok_or(RejectReason::Overflow)?;
+        let held_total = self.held_total.checked_sub(released).ok_or(RejectReason::Overflow)?;
+
+        account.available = available;
+        account.held = held;
+        disputed_tx.held -= released;
+        self.held_total = held_total;
+        if disputed_tx.held == A::ZERO {
+            disputed_tx.dispute_status = DisputeStatus::Resolved;
+        }
+        self.dirty.insert(tx.client_id);
+        Ok(())
+    }
+
+    /// Handles a chargeback transaction.
+    /// Moves funds from held to withdrawn and, unless the `ChargebackPolicy` says otherwise,
+    /// freezes the client's account. If the row carries an amount, only that much is charged
+    /// back and the rest of the held funds is released to available; the transaction is
+    /// charged back either way. Charging back a debit reverses it: the charged funds are
+    /// credited to available and the rest are dropped.
+    /// The referenced transaction must exist and be under dispute.
+    pub fn handle_chargeback(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        let disputed_tx = self
+            .transactions
+            .get_mut(&tx.tx_id)
+            .ok_or(RejectReason::UnknownTransaction)?;
+        if disputed_tx.client_id != tx.client_id {
+            return Err(RejectReason::ClientMismatch);
+        }
+        if disputed_tx.dispute_status != DisputeStatus::Disputed {
+            return Err(undisputed_reason(disputed_tx.dispute_status));
+        }
+        let charged = match tx.amount {
+            Some(amount) if amount <= A::ZERO => return Err(RejectReason::NonPositiveAmount),
+            Some(amount) if amount > disputed_tx.held => return Err(RejectReason::ExceedsHeldAmount),
+            Some(amount) => amount,
+            None => disputed_tx.held,
+        };
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        if account.locked && !self.config.allow_dispute_on_locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        if account.held < disputed_tx.held {
+            return Err(RejectReason::InsufficientHeldFunds);
+        }
+        let credited = if disputed_tx.is_debit() {
+            charged
+        } else {
+            disputed_tx.held - charged
+        };
+        let available = account.available.checked_add(credited).ok_or(RejectReason::Overflow)?;
+        let held = account.held.checked_sub(disputed_tx.held).ok_or(RejectReason::Overflow)?;
+        let held_total = self.held_total.checked_sub(disputed_tx.held).ok_or(RejectReason::Overflow)?;
+
+        account.available = available;
+        account.held = held;
+        self.held_total = held_total;
+        disputed_tx.held = A::ZERO;
+        disputed_tx.dispute_status = DisputeStatus::ChargedBack;
+        if self.config.chargeback_policy == ChargebackPolicy::Lock {
+            account.locked = true;
+            if let Some(hook) = &mut self.lock_hook {
+                hook(tx.client_id, account);
+            }
+        }
+        self.dirty.insert(tx.client_id);
+        Ok(())
+    }
+
+    /// Handles a reversal transaction.
+    /// Removes the funds credited by a prior deposit from the client's available funds and
+    /// marks the deposit as reversed. Any fee charged on the deposit is kept.
+    /// The referenced transaction must be a deposit that is not under dispute, charged back or
+    /// already reversed. If the client has already spent the funds, the configured
+    /// `ReversalPolicy` decides whether the reversal is rejected or overdraws the account.
+    pub fn handle_reversal(&mut self, tx: InputTransaction<A>) -> Result<(), RejectReason> {
+        self.check_no_amount(&tx)?;
+        let reversed_tx = self
+            .transactions
+            .get_mut(&tx.tx_id)
+            .ok_or(RejectReason::UnknownTransaction)?;
+        if reversed_tx.client_id != tx.client_id {
+            return Err(RejectReason::ClientMismatch);
+        }
+        if reversed_tx.dispute_status == DisputeStatus::Rejected {
+            return Err(RejectReason::RejectedDeposit);
+        }
+        if reversed_tx.transaction_type != TransactionType::Deposit {
+            return Err(RejectReason::NotADeposit);
+        }
+        if !matches!(
+            reversed_tx.dispute_status,
+            DisputeStatus::NotDisputed | DisputeStatus::Resolved
+        ) {
+            return Err(RejectReason::InvalidDisputeStatus);
+        }
+
+        let account = self
+            .accounts
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        if account.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        if !account.accepts_currency(tx.currency.as_deref()) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        if account.available < reversed_tx.amount
+            && self.config.reversal_policy == ReversalPolicy::RejectIfSpent
+        {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        let available = account.available.checked_sub(reversed_tx.amount).ok_or(RejectReason::Overflow)?;
+
+        account.available = available;
+        reversed_tx.dispute_status = DisputeStatus::Reversed;
+        self.dirty.insert(tx.client_id);
+        Ok(())
+    }
+
+    /// In strict mode, rejects a dispute or reversal that carries an amount. These rows never
+    /// have an amount, so one being present indicates a producer bug. Resolves and chargebacks
+    /// are not checked, since their amount makes them partial.
+    fn check_no_amount(&self, tx: &InputTransaction<A>) -> Result<(), RejectReason> {
+        if self.config.strict_amounts && tx.amount.is_some() {
+            return Err(RejectReason::UnexpectedAmount);
+        }
+        Ok(())
+    }
+
+    /// Merges another engine, e.g. one that processed a separate partition of the input, into
+    /// this one.
+    ///
+    /// A client present in both engines gets a single account whose available and held funds
+    /// are the sums of both, locked if either was locked. The other engine is taken to have
+    /// processed the later input, so its reference for the client, if any, wins. Since held
+    /// funds are tracked per transaction, this is only safe when the engines stored disjoint
+    /// transactions: if any transaction ID appears in both, a client's accounts established
+    /// different currencies or their combined balances would overflow, nothing is merged and
+    /// an error is returned. Fees, counts and recorded actions are combined, and this engine's
+    /// configuration is kept. Buffered orphan disputes are combined too: those whose
+    /// transaction is now known are applied, and those whose transaction either engine evicted
+    /// are rejected as unknown. If one of them is rejected with a reason that halts
+    /// processing, the engines are already merged when the error is returned.
+    pub fn merge(&mut self, other: PaymentEngine<A, S, T>) -> Result<(), MergeError> {
+        let mut duplicates: Vec<_> = other
+            .transactions
+            .keys()
+            .filter(|tx_id| self.transactions.contains_key(tx_id))
+            .collect();
+        duplicates.sort_unstable();
+        if let Some(&&tx_id) = duplicates.first() {
+            return Err(MergeError::DuplicateTransaction(tx_id));
+        }
+        let mut mismatched: Vec<_> = other
+            .accounts
+            .iter()
+            .filter(|(client_id, account)| {
+                self.accounts.get(client_id).is_some_and(|existing| {
+                    existing.currency.is_some() && !existing.accepts_currency(account.currency.as_deref())
+                })
+            })
+            .map(|(&client_id, _)| client_id)
+            .collect();
+        mismatched.sort_unstable();
+        if let Some(&client_id) = mismatched.first() {
+            return Err(MergeError::CurrencyMismatch(client_id));
+        }
+        let mut balances = HashMap::new();
+        let mut overflowing = Vec::new();
+        for (&client_id, account) in other.accounts.iter() {
+            let Some(existing) = self.accounts.get(&client_id) else {
+                continue;
+            };
+            match merged_balances(existing, account) {
+                Some(merged) => {
+                    balances.insert(client_id, merged);
+                }
+                None => overflowing.push(client_id),
+            }
+        }
+        overflowing.sort_unstable();
+        if let Some(&client_id) = overflowing.first() {
+            return Err(MergeError::Overflow(Some(client_id)));
+        }
+        let totals = self
+            .fees_collected
+            .checked_add(other.fees_collected)
+            .zip(self.held_total.checked_add(other.held_total))
+            .zip(self.net_deposits.checked_add(other.net_deposits))
+            .zip(self.evictions.checked_add(other.evictions));
+        let Some((((fees_collected, held_total), net_deposits), evictions)) = totals else {
+            return Err(MergeError::Overflow(None));
+        };
+
+        for (client_id, account) in other.accounts {
+            match self.accounts.get_mut(&client_id) {
+                Some(existing) => {
+                    if let Some((available, held, pending_fees)) = balances.remove(&client_id) {
+                        existing.available = available;
+                        existing.held = held;
+                        existing.pending_fees = pending_fees;
+                    }
+                    existing.disputes = existing.disputes.saturating_add(account.disputes);
+                    existing.locked |= account.locked;
+                    if existing.currency.is_none() {
+                        existing.currency = account.currency;
+                    }
+                    if account.last_reference.is_some() {
+                        existing.last_reference = account.last_reference;
+                    }
+                }
+                None => {
+                    self.accounts.insert(client_id, account);
+                }
+            }
+        }
+        for (tx_id, stored) in other.transactions {
+            self.transactions.insert(tx_id, stored);
+        }
+        self.fees_collected = fees_collected;
+        self.dirty.extend(other.dirty);
+        self.counts.add(&other.counts);
+        self.admin_actions.extend(other.admin_actions);
+        self.audit_log.extend(other.audit_log);
+        self.interest_credits.extend(other.interest_credits);
+        self.retained.extend(other.retained);
+        self.evictions = evictions;
+        self.evicted.extend(other.evicted);
+        self.held_total = held_total;
+        self.net_deposits = net_deposits;
+        for peak in [other.peak_held, self.held_total] {
+            if peak > self.peak_held {
+                self.peak_held = peak;
+            }
+        }
+        for (tx_id, orphans) in other.pending_disputes {
+            self.pending_disputes.entry(tx_id).or_default().extend(orphans);
+        }
+        // Orphans buffered by one engine for a transaction the other evicted will never match
+        let stale: Vec<_> = self
+            .pending_disputes
+            .keys()
+            .copied()
+            .filter(|tx_id| self.evicted.contains(tx_id) && !self.transactions.contains_key(tx_id))
+            .collect();
+        for tx_id in stale {
+            for orphan in self.pending_disputes.remove(&tx_id).unwrap_or_default() {
+                self.counts.record(orphan.transaction_type, false);
+            }
+        }
+        for (client_id, seq) in other.last_seq {
+            let last = self.last_seq.entry(client_id).or_insert(seq);
+            *last = (*last).max(seq);
+        }
+        let matched: Vec<_> = self
+            .pending_disputes
+            .keys()
+            .copied()
+            .filter(|tx_id| self.transactions.contains_key(tx_id))
+            .collect();
+        for tx_id in matched {
+            self.apply_pending(tx_id).map_err(|reason| MergeError::Rejected { tx_id, reason })?;
+        }
+        Ok(())
+    }
+
+    /// Creates a client's account with the given opening balance, e.g. when migrating from
+    /// another system instead of replaying the client's history. Later transactions apply on
+    /// top of it, and it is exported like any other account.
+    ///
+    /// The account starts with no held funds: held funds are tracked per disputed
+    /// transaction, and an opening balance has none behind it to resolve or charge back.
+    /// Fails if the client already has an account.
+    pub fn set_opening_balance(
+        &mut self,
+        client: ClientId,
+        available: A,
+        locked: bool,
+    ) -> Result<(), OpeningBalanceError> {
+        if self.accounts.contains_key(&client) {
+            return Err(OpeningBalanceError::ExistingAccount(client));
+        }
+        let net_deposits = match self.config.held_check {
+            Some(_) => self
+                .net_deposits
+                .checked_add(available)
+                .ok_or(OpeningBalanceError::Overflow(client))?,
+            None => self.net_deposits,
+        };
+
+        let mut account = Account::new(client);
+        account.available = available;
+        account.locked = locked;
+        self.accounts.insert(client, account);
+        self.dirty.insert(client);
+        self.net_deposits = net_deposits;
+        Ok(())
+    }
+
+    /// Overwrites a stored transaction's dispute status, e.g. to close a dispute opened in
+    /// error. The action is recorded in `admin_actions`.
+    ///
+    /// **This moves no funds.** The account's `available` and `held` balances and the
+    /// transaction's held amount are left as they are, so the caller must correct them
+    /// separately. Until then `held` no longer matches the disputed transactions, which
+    /// `disputed_exposure` asserts in debug builds, and later resolves or chargebacks act on
+    /// the forced status. Only available with the `admin` feature.
+    #[cfg(feature = "admin")]
+    pub fn force_dispute_status(
+        &mut self,
+        tx_id: TransactionId,
+        status: DisputeStatus,
+    ) -> Result<(), DisputeStatusError> {
+        let stored = self
+            .transactions
+            .get_mut(&tx_id)
+            .ok_or(DisputeStatusError::UnknownTransaction(tx_id))?;
+        let from = stored.dispute_status;
+        stored.dispute_status = status;
+        self.admin_actions.push(AdminAction::ForceDisputeStatus { tx_id, from, to: status });
+        Ok(())
+    }
+
+    /// Unlocks a client's account after manual review, so that it accepts transactions again.
+    /// The action is recorded in `admin_actions`.
+    pub fn unlock_account(&mut self, client: ClientId) -> Result<(), UnlockError> {
+        let account = self
+            .accounts
+            .get_mut(&client)
+            .ok_or(UnlockError::UnknownClient(client))?;
+        if !account.locked {
+            return Err(UnlockError::NotLocked(client));
+        }
+
+        account.locked = false;
+        self.dirty.insert(client);
+        self.admin_actions.push(AdminAction::Unlock(client));
+        Ok(())
+    }
+
+    /// Credits interest to every unlocked account: `available * rate`, rounded per the
+    /// configured rounding mode and scale. Locked accounts, accounts without positive
+    /// available funds and accounts whose balance the credit would overflow are skipped, as
+    /// are credits that would overflow the net deposits tracked for `held_check`. Each credit
+    /// is recorded in `interest_credits`.
+    pub fn accrue_interest(&mut self, rate: Decimal) {
+        let mut credits = Vec::new();
+        for account in self.accounts.values() {
+            if account.locked {
+                continue;
+            }
+            let Some(interest) = account.available.to_decimal().checked_mul(rate) else {
+                continue;
+            };
+            let amount = A::from_decimal(self.round(interest));
+            if amount > A::ZERO && account.available.checked_add(amount).is_some() {
+                credits.push(InterestCredit { client_id: account.id, amount });
+            }
+        }
+        credits.sort_by_key(|credit| credit.client_id);
+
+        for credit in credits {
+            let net_deposits = match self.config.held_check {
+                Some(_) => self.net_deposits.checked_add(credit.amount),
+                None => Some(self.net_deposits),
+            };
+            let Some(account) = self.accounts.get_mut(&credit.client_id) else {
+                continue;
+            };
+            let (Some(available), Some(net_deposits)) = (account.available.checked_add(credit.amount), net_deposits)
+            else {
+                continue;
+            };
+            account.available = available;
+            self.net_deposits = net_deposits;
+            self.dirty.insert(credit.client_id);
+            self.interest_credits.push(credit);
+        }
+    }
+
+    /// Moves residual held funds back to available. An unlocked account's held funds are
+    /// swept when they are nonzero but smaller in magnitude than `threshold` and none of the
+    /// client's transactions is under dispute. Accounts whose available funds the sweep would
+    /// overflow are skipped. Returns the amount swept per client, ordered by client ID.
+    ///
+    /// Processing never leaves such residue, since held funds are tracked per disputed
+    /// transaction, and `set_opening_balance` sets none. It only arises in accounts
+    /// edited directly through `accounts`, e.g. restored from serialized state.
+    pub fn sweep_dust(&mut self, threshold: Decimal) -> Vec<(ClientId, A)> {
+        let disputing: HashSet<ClientId> = self
+            .transactions
+            .values()
+            .filter(|t| t.dispute_status == DisputeStatus::Disputed)
+            .map(|t| t.client_id)
+            .collect();
+        let dusty: Vec<ClientId> = self
+            .accounts
+            .values()
+            .filter(|account| account.held != A::ZERO && account.held.abs().to_decimal() < threshold)
+            .filter(|account| !account.locked && !disputing.contains(&account.id))
+            .map(|account| account.id)
+            .collect();
+
+        // The residue never entered `held_total`, so it is left alone
+        let mut swept = Vec::new();
+        for client in dusty {
+            let Some(account) = self.accounts.get_mut(&client) else {
+                continue;
+            };
+            let Some(available) = account.available.checked_add(account.held) else {
+                continue;
+            };
+            swept.push((client, account.held));
+            account.available = available;
+            account.held = A::ZERO;
+            self.dirty.insert(client);
+        }
+        swept.sort_unstable_by_key(|&(client, _)| client);
+        swept
+    }
+
+    /// Returns the interest credited by `accrue_interest`, oldest first.
+    pub fn interest_credits(&self) -> &[InterestCredit<A>] {
+        &self.interest_credits
+    }
+
+    /// Returns the total interest credited across all accounts.
+    pub fn interest_paid(&self) -> A {
+        self.interest_credits.iter().map(|credit| credit.amount).sum()
+    }
+
+    /// Returns the audit log of successfully applied transactions, oldest first. The log is
+    /// empty unless `EngineConfig::audit` is enabled. Administrative changes such as
+    /// `unlock_account` or `accrue_interest` are not logged; see `replay_audit_with_config`.
+    pub fn audit_entries(&self) -> &[AuditEntry] {
+        &self.audit_log
+    }
+
+    /// Returns a client's available funds as they stood right after the given deposit,
+    /// withdrawal or adjustment was applied, reconstructed from the audit log.
+    ///
+    /// Returns `None` if auditing is disabled, or if the transaction was not applied to that
+    /// client's account. This scans the audit log, so it is linear in the number of applied
+    /// transactions; nothing beyond the log is stored.
+    pub fn balance_at(&self, client: ClientId, up_to_tx: TransactionId) -> Option<Decimal> {
+        self.audit_log
+            .iter()
+            .find(|entry| {
+                entry.client_id == client
+                    && entry.tx_id == up_to_tx
+                    && matches!(
+                        entry.transaction_type,
+                        TransactionType::Deposit
+                            | TransactionType::Withdrawal
+                            | TransactionType::Adjustment
+                    )
+            })
+            .map(|entry| entry.available_after)
+    }
+
+    /// Returns the manual administrative actions taken on the engine, oldest first.
+    pub fn admin_actions(&self) -> &[AdminAction] {
+        &self.admin_actions
+    }
+
+    /// Returns the ids of all known clients in ascending order, matching the export order.
+    pub fn client_ids(&self) -> impl Iterator<Item = ClientId> {
+        let mut ids: Vec<_> = self.accounts.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+    }
+
+    /// Compares this engine's state with another's, returning the clients whose balances,
+    /// lock state or transaction dispute statuses differ, sorted by client ID. Equal states
+    /// produce no differences.
+    pub fn diff(&self, other: &PaymentEngine<A, S, T>) -> Vec<AccountDiff<A>> {
+        let new_diff = |client_id| AccountDiff {
+            client_id,
+            left: self.accounts.get(&client_id).map(Account::snapshot),
+            right: other.accounts.get(&client_id).map(Account::snapshot),
+            dispute_statuses: Vec::new(),
+        };
+        let mut diffs = BTreeMap::new();
+
+        for &client_id in self.accounts.keys().chain(other.accounts.keys()) {
+            let left = self.accounts.get(&client_id).map(Account::snapshot);
+            let right = other.accounts.get(&client_id).map(Account::snapshot);
+            if left != right {
+                diffs.entry(client_id).or_insert_with(|| new_diff(client_id));
+            }
+        }
+
+        let mut tx_ids: Vec<_> = self.transactions.keys().chain(other.transactions.keys()).collect();
+        tx_ids.sort_unstable();
+        tx_ids.dedup();
+        for tx_id in tx_ids {
+            let left = self.transactions.get(tx_id);
+            let right = other.transactions.get(tx_id);
+            let left_status = left.map(|t| t.dispute_status);
+            let right_status = right.map(|t| t.dispute_status);
+            if left_status != right_status {
+                let Some(client_id) = left.or(right).map(|t| t.client_id) else {
+                    continue;
+                };
+                diffs
+                    .entry(client_id)
+                    .or_insert_with(|| new_diff(client_id))
+                    .dispute_statuses
+                    .push((*tx_id, left_status, right_status));
+            }
+        }
+
+        diffs.into_values().collect()
+    }
+
+    /// Returns the number of known clients.
+    pub fn num_clients(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Returns the highest total of held funds across all accounts at any point so far, as
+    /// changed by disputes, resolves and chargebacks. After a `merge` it is the larger of the
+    /// two engines' peaks and the merged total, as the engines' histories cannot be
+    /// interleaved.
+    pub fn peak_held(&self) -> A {
+        self.peak_held
+    }
+
+    /// Returns the total amount of a client's transactions that are currently under dispute.
+    /// This is the client's exposure pending resolution and must always equal the account's
+    /// `held` funds, which is checked in debug builds.
+    pub fn disputed_exposure(&self, client: ClientId) -> A {
+        let exposure = self
+            .transactions
+            .values()
+            .filter(|t| t.client_id == client && t.dispute_status == DisputeStatus::Disputed)
+            .map(|t| t.held)
+            .sum();
+
+        debug_assert_eq!(
+            exposure,
+            self.accounts.get(&client).map_or(A::ZERO, |a| a.held),
+            "disputed exposure does not match held funds for client {}",
+            client
+        );
+        exposure
+    }
+
+    /// Classifies a client's account, or returns `None` if the client has no account. This
+    /// scans the stored transactions for active disputes, so it is linear in their number.
+    pub fn account_status(&self, client: ClientId) -> Option<AccountStatus> {
+        let account = self.accounts.get(&client)?;
+        let has_active_dispute = !account.locked
+            && self
+                .transactions
+                .values()
+                .any(|t| t.client_id == client && t.dispute_status == DisputeStatus::Disputed);
+        Some(account.status(has_active_dispute))
+    }
+
+    /// Calculates the engine-wide net position in a single pass over the accounts.
+    ///
+    /// Panics if a sum overflows.
+    pub fn net_position(&self) -> NetPosition<A> {
+        let mut position = NetPosition {
+            available: A::ZERO,
+            held: A::ZERO,
+            unlocked: A::ZERO,
+            locked: A::ZERO,
+            total: A::ZERO,
+        };
+        for account in self.accounts.values() {
+            let total = account.total();
+            position.available += account.available;
+            position.held += account.held;
+            position.total += total;
+            if account.locked {
+                position.locked += total;
+            } else {
+                position.unlocked += total;
+            }
+        }
+        position
+    }
+
+    /// Returns the IDs of all clients whose account is overdrawn, in ascending order.
+    pub fn overdrawn_clients(&self) -> Vec<ClientId> {
+        self.sorted_accounts()
+            .into_iter()
+            .filter(|account| account.is_overdrawn())
+            .map(|account| account.id)
+            .collect()
+    }
+
+    /// Writes the final state of all accounts to a given writer in CSV format.
+    ///
+    /// With `checksum_row` configured, a final `checksum,,,<sum>,` row follows the accounts,
+    /// where the sum is taken over the exported totals. If any account has a reference from
+    /// the input's `reference` column, a trailing `reference` column holds each account's
+    /// latest one, empty for accounts without.
+    pub fn export_accounts<W: io::Write>(&self, writer: W) -> Result<(), ExportError> {
+        self.export_accounts_with(writer, &CsvFormat::default())
+    }
+
+    /// Writes the final state of all accounts like `export_accounts`, in the given CSV
+    /// dialect and with the given header names.
+    pub fn export_accounts_with<W: io::Write>(
+        &self,
+        writer: W,
+        format: &CsvFormat,
+    ) -> Result<(), ExportError> {
+        self.write_accounts(writer, format, self.sorted_accounts())
+    }
+
+    /// Writes the final state of all accounts like `export_accounts`, ordered by the given
+    /// key. Ties are broken by client ID.
+    pub fn export_accounts_sorted_by<W: io::Write>(&self, writer: W, key: SortKey) -> Result<(), ExportError> {
+        let accounts = self.sorted_accounts();
+        let mut keyed = Vec::with_capacity(accounts.len());
+        for account in accounts {
+            let value = match key {
+                SortKey::ClientId => A::ZERO,
+                SortKey::TotalDesc => account
+                    .checked_total_with(self.config.total_mode)
+                    .ok_or(ExportError::TotalOverflow(account.id))?,
+                SortKey::HeldDesc => account.held,
+                SortKey::AvailableDesc => account.available,
+            };
+            keyed.push((value, account));
+        }
+        // The sort is stable, so ties keep their client ID order
+        keyed.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        let accounts = keyed.into_iter().map(|(_, account)| account).collect();
+        self.write_accounts(writer, &CsvFormat::default(), accounts)
+    }
+
+    /// Writes the given accounts in order, followed by the checksum row if configured.
+    fn write_accounts<W: io::Write>(
+        &self,
+        writer: W,
+        format: &CsvFormat,
+        accounts: Vec<&Account<A>>,
+    ) -> Result<(), ExportError> {
+        let mut wtr = csv::WriterBuilder::new()
+            .delimiter(format.delimiter)
+            .quote_style(format.quote_style)
+            .has_headers(false)
+            .from_writer(writer);
+        let mut checksum = Decimal::ZERO;
+        let outputs = self.output_accounts(&accounts)?;
+        let references = accounts.iter().any(|account| account.last_reference.is_some());
+        let mut headers: Vec<&str> = format.headers.iter().map(String::as_str).collect();
+        if references {
+            headers.push("reference");
+        }
+        wtr.write_record(headers)
+            .map_err(ExportError::from_csv_write)?;
+        for (account, mut output) in accounts.into_iter().zip(outputs) {
+            checksum += output.total;
+            if references {
+                output.reference = Some(account.last_reference.clone().unwrap_or_default());
+            }
+            wtr.serialize(output)
+                .map_err(|err| ExportError::from_csv(account.id, err))?;
+        }
+        if self.config.checksum_row {
+            let checksum = format_amount(checksum);
+            let mut row = vec!["checksum", "", "", &checksum, ""];
+            if references {
+                row.push("");
+            }
+            wtr.write_record(row)
+                .map_err(ExportError::from_csv_write)?;
+        }
+        wtr.flush().map_err(ExportError::Flush)?;
+        Ok(())
+    }
+
+    /// Writes only the accounts of the given clients, in the same format and order as
+    /// `export_accounts`. Clients without an account are skipped; the header is written even
+    /// if no account matches.
+    pub fn export_accounts_filtered<W: io::Write>(
+        &self,
+        writer: W,
+        clients: &HashSet<ClientId>,
+    ) -> Result<(), ExportError> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+        wtr.write_record(OUTPUT_COLUMNS)
+            .map_err(ExportError::from_csv_write)?;
+        for account in self.sorted_accounts() {
+            if clients.contains(&account.id) {
+                wtr.serialize(self.output_account(account)?)
+                    .map_err(|err| ExportError::from_csv(account.id, err))?;
+            }
+        }
+        wtr.flush().map_err(ExportError::Flush)?;
+        Ok(())
+    }
+
+    /// Returns the accounts as a JSON array of objects with the same fields, order and amount
+    /// formatting as `export_accounts`, for embedding in a larger JSON document. The
+    /// `reference` column is omitted; each account's latest reference is in
+    /// `Account::last_reference`.
+    pub fn accounts_to_json_value(&self) -> Result<serde_json::Value, ExportError> {
+        let accounts = self.output_accounts(&self.sorted_accounts())?;
+        Ok(serde_json::to_value(accounts).expect("account output always serializes to JSON"))
+    }
+
+    /// Writes the accounts to a given writer as a JSON array, in the same form as
+    /// `accounts_to_json_value`. Each account is serialized straight to the writer, so the
+    /// document is never held in memory. Only sum-preserving rounding, which needs every
+    /// total up front, converts all accounts before writing the first.
+    pub fn export_accounts_json<W: io::Write>(&self, writer: W) -> Result<(), ExportError> {
+        let accounts = self.sorted_accounts();
+        let preconverted = match (self.config.scale, self.config.preserve_sums) {
+            (Some(_), true) => Some(self.output_accounts(&accounts)?),
+            _ => None,
+        };
+        let mut serializer = serde_json::Serializer::new(io::BufWriter::new(writer));
+        let mut seq = serializer
+            .serialize_seq(Some(accounts.len()))
+            .map_err(|err| ExportError::from_json(None, err))?;
+        let mut preconverted = preconverted.map(Vec::into_iter);
+        for account in accounts {
+            let output = match preconverted.as_mut().and_then(Iterator::next) {
+                Some(output) => output,
+                None => self.output_account(account)?,
+            };
+            seq.serialize_element(&output)
+                .map_err(|err| ExportError::from_json(Some(account.id), err))?;
+        }
+        seq.end().map_err(|err| ExportError::from_json(None, err))?;
+        io::Write::flush(&mut serializer.into_inner()).map_err(ExportError::Flush)?;
+        Ok(())
+    }
+
+    /// Writes the stored transactions to a given writer in CSV format, sorted by transaction
+    /// ID, with columns `tx,client,amount,dispute_status`. The amount is the one credited or
+    /// debited, excluding any fee. Transactions evicted by the retention window are not
+    /// included; the header is written even if no transaction is stored.
+    pub fn export_transactions<W: io::Write>(&self, writer: W) -> Result<(), ExportError> {
+        let mut transactions: Vec<_> = self.transactions.iter().collect();
+        transactions.sort_unstable_by_key(|(tx_id, _)| **tx_id);
+
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+        wtr.write_record(TRANSACTION_COLUMNS)
+            .map_err(ExportError::from_csv_write)?;
+        for (&tx_id, tx) in transactions {
+            let output = OutputTransaction {
+                tx: tx_id,
+                client: tx.client_id,
+                amount: tx.amount.to_decimal(),
+                dispute_status: tx.dispute_status,
+            };
+            wtr.serialize(output)
+                .map_err(|err| ExportError::from_csv(tx.client_id, err))?;
+        }
+        wtr.flush().map_err(ExportError::Flush)?;
+        Ok(())
+    }
+
+    /// Writes only the accounts whose balances or lock state changed since the previous call,
+    /// in the same format and order as `export_accounts`, then clears the set of changed
+    /// accounts. The header is written even if no account changed. The set is left untouched
+    /// if writing fails.
+    pub fn export_dirty_accounts<W: io::Write>(&mut self, writer: W) -> Result<(), ExportError> {
+        let accounts = self
+            .sorted_accounts()
+            .into_iter()
+            .filter(|account| self.dirty.contains(&account.id))
+            .collect();
+        self.write_accounts(writer, &CsvFormat::default(), accounts)?;
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Writes a human-readable report of all accounts to a given writer.
+    ///
+    /// Accounts are listed in the same order as `export_accounts`, as aligned columns under a
+    /// header, followed by a row totalling each balance across all accounts. References are
+    /// omitted, as in `accounts_to_json_value`.
+    pub fn print_report<W: io::Write>(&self, mut writer: W) -> Result<(), ExportError> {
+        let mut rows = vec![OUTPUT_COLUMNS.map(String::from)];
+        let (mut available, mut held, mut total) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        for account in self.sorted_accounts() {
+            let output = self.output_account(account)?;
+            let sums = available
+                .checked_add(output.available)
+                .zip(held.checked_add(output.held))
+                .zip(total.checked_add(output.total));
+            ((available, held), total) = sums.ok_or(ExportError::TotalOverflow(account.id))?;
+            rows.push([
+                output.id.to_string(),
+                format_amount(output.available),
+                format_amount(output.held),
+                format_amount(output.total),
+                output.locked.to_string(),
+            ]);
+        }
+        rows.push([
+            "total".to_string(),
+            format_amount(available),
+            format_amount(held),
+            format_amount(total),
+            String::new(),
+        ]);
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        for row in &rows {
+            let line: Vec<_> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:>width$}", cell))
+                .collect();
+            writeln!(writer, "{}", line.join("  ").trim_end())
+                .map_err(|source| ExportError::Io { client_id: None, source })?;
+        }
+        writer.flush().map_err(ExportError::Flush)?;
+        Ok(())
+    }
+
+    /// Returns all accounts sorted by client ID, the order used for all output.
+    fn sorted_accounts(&self) -> Vec<&Account<A>> {
+        let mut accounts: Vec<_> = self.accounts.values().collect();
+        accounts.sort_by_key(|a| a.id);
+        accounts
     }
 }
\ No newline at end of file
@@ -0,0 +1,165 @@
+use crate::binary;
+use crate::engine::{InputTransaction, PaymentEngine, TransactionType};
+use rand::{seq::SliceRandom, Rng};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::io;
+
+/// Generates a random sequence of transactions for clients `1..=num_clients`, together with
+/// the engine state that applying them produces.
+///
+/// Disputes, resolves and chargebacks always reference a stored transaction of the same
+/// client, so a good share of them are applied rather than rejected.
+pub fn generate<R: Rng>(
+    rng: &mut R,
+    num_clients: u16,
+    num_transactions: u32,
+) -> (Vec<InputTransaction>, PaymentEngine) {
+    let mut transactions = Vec::new();
+    let mut engine = PaymentEngine::new();
+
+    let mut valid_tx_ids: Vec<u32> = Vec::new();
+
+    for tx_id in 1..=num_transactions {
+        let client_id = rng.gen_range(1..=num_clients);
+        let transaction_type = choose_transaction_type(rng, &valid_tx_ids);
+
+        let tx = match transaction_type {
+            TransactionType::Deposit => {
+                // Generate amounts with varying decimal precision (2-4 decimal places)
+                let scale = rng.gen_range(2..=4);
+                let max_value = 10_i64.pow(scale + 2); // Adjust range based on scale
+                let amount = Decimal::new(rng.gen_range(1..max_value), scale);
+                valid_tx_ids.push(tx_id);
+                InputTransaction {
+                    transaction_type,
+                    client_id,
+                    tx_id,
+                    amount: Some(amount),
+                    currency: None,
+                    reference: None,
+                    seq: None,
+                }
+            }
+            TransactionType::Withdrawal | TransactionType::Adjustment => {
+                // Generate amounts with varying decimal precision (2-4 decimal places)
+                let scale = rng.gen_range(2..=4);
+                let max_value = 10_i64.pow(scale + 1); // Smaller range for withdrawals
+                let amount = Decimal::new(rng.gen_range(1..max_value), scale);
+                InputTransaction {
+                    transaction_type,
+                    client_id,
+                    tx_id,
+                    amount: Some(amount),
+                    currency: None,
+                    reference: None,
+                    seq: None,
+                }
+            }
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Reversal => {
+                let target_tx_id = loop {
+                    let id = *valid_tx_ids.choose(rng).unwrap();
+                    if engine.transactions.contains_key(&id) {
+                        break id;
+                    }
+                };
+
+                InputTransaction {
+                    transaction_type,
+                    client_id: engine.transactions.get(&target_tx_id).unwrap().client_id,
+                    tx_id: target_tx_id,
+                    amount: None,
+                    currency: None,
+                    reference: None,
+                    seq: None,
+                }
+            }
+        };
+
+        // Process the transaction with our engine to calculate the expected state
+        let _ = engine.apply(tx.clone());
+        transactions.push(tx);
+    }
+
+    (transactions, engine)
+}
+
+/// Writes transactions one at a time in the engine's CSV input format.
+///
+/// Rows are serialized from `InputTransaction` itself, so the type names and columns written
+/// are exactly those the engine deserializes. All generators write through it.
+pub struct TransactionWriter<W: io::Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: io::Write> TransactionWriter<W> {
+    /// Creates a writer. The header row is written along with the first transaction.
+    pub fn new(writer: W) -> Self {
+        Self { wtr: csv::Writer::from_writer(writer) }
+    }
+
+    /// Writes a single transaction.
+    pub fn write(&mut self, tx: &InputTransaction) -> csv::Result<()> {
+        self.wtr.serialize(tx)
+    }
+
+    /// Flushes any buffered rows to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+/// Writes transactions to a given writer in the engine's CSV input format.
+pub fn write_transactions<W: io::Write>(
+    transactions: &[InputTransaction],
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = TransactionWriter::new(writer);
+    for tx in transactions {
+        wtr.write(tx)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes transactions to the given writer in the binary format read by
+/// `PaymentEngine::process_transactions_binary`.
+pub fn write_transactions_binary<W: io::Write>(
+    transactions: &[InputTransaction],
+    mut writer: W,
+) -> io::Result<()> {
+    for tx in transactions {
+        writer.write_all(&binary::encode(tx))?;
+    }
+    writer.flush()
+}
+
+/// Picks the type of the next generated transaction, weighted towards deposits and
+/// withdrawals. Only deposits are picked until `valid_tx_ids` holds a transaction that
+/// others can reference.
+pub fn choose_transaction_type(rng: &mut impl Rng, valid_tx_ids: &[u32]) -> TransactionType {
+    if valid_tx_ids.is_empty() {
+        return TransactionType::Deposit;
+    }
+
+    *[
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ]
+    .choose_weighted(rng, |item| match item {
+        TransactionType::Deposit => 40,
+        TransactionType::Withdrawal => 30,
+        TransactionType::Dispute => 10,
+        TransactionType::Resolve => 10,
+        TransactionType::Chargeback => 10,
+        TransactionType::Reversal => 0,
+        TransactionType::Adjustment => 0,
+    })
+    .unwrap()
+}
@@ -1 +1,9 @@
-pub mod engine; 
\ No newline at end of file
+pub mod amount;
+pub mod audit;
+pub mod binary;
+pub mod engine;
+pub mod generator;
+pub mod store;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+pub mod validate;
@@ -1,20 +1,256 @@
-use rs_accountant::engine::PaymentEngine;
+use rs_accountant::engine::{process_files_with_config, EngineConfig};
 use std::error::Error;
-use std::fs::File;
+use std::fmt;
 use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: cargo run -- <input_file.csv>");
-        return Err("Invalid arguments".into());
+const USAGE: &str =
+    "Usage: cargo run -- [--format csv|json|human] [--human] [--stats] [--retain <count>] <input_file.csv>...";
+
+/// An error in the command line arguments.
+///
+/// Each kind exits with its own code so that wrapper scripts can tell usage errors apart
+/// from failures while processing; misused options share one:
+///
+/// | Code | Meaning                                                                        |
+/// |------|--------------------------------------------------------------------------------|
+/// | 0    | Success                                                                        |
+/// | 1    | Processing or writing the output failed                                        |
+/// | 2    | No input file was given (`MissingPath`)                                        |
+/// | 3    | An option is unknown (`UnknownOption`), repeated (`DuplicateOption`) or lacks  |
+/// |      | a valid value (`MissingValue`)                                                 |
+/// | 4    | An input file does not exist (`FileNotFound`)                                  |
+/// | 5    | The output format is not recognized (`UnknownFormat`)                          |
+#[derive(Debug)]
+enum CliError {
+    /// No input file was given.
+    MissingPath,
+    /// An option was given more than once, or after another that sets the same thing, as
+    /// `--human` and `--format` do.
+    DuplicateOption(String),
+    /// An option was given without a value, or with one it cannot parse.
+    MissingValue(String),
+    /// An argument starting with `--` names no known option.
+    UnknownOption(String),
+    /// An input file does not exist.
+    FileNotFound(PathBuf),
+    /// The value of `--format` is not a known output format.
+    UnknownFormat(String),
+}
+
+impl CliError {
+    /// Returns the process exit code for the error.
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::MissingPath => 2,
+            CliError::DuplicateOption(_) | CliError::MissingValue(_) | CliError::UnknownOption(_) => 3,
+            CliError::FileNotFound(_) => 4,
+            CliError::UnknownFormat(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::MissingPath => write!(f, "no input file given"),
+            CliError::DuplicateOption(option) => write!(f, "`{}` sets an option that was already given", option),
+            CliError::MissingValue(option) => write!(f, "`{}` expects a valid value", option),
+            CliError::UnknownOption(option) => write!(f, "unknown option `{}`", option),
+            CliError::FileNotFound(path) => write!(f, "{}: file not found", path.display()),
+            CliError::UnknownFormat(format) => {
+                write!(f, "unknown output format `{}`, expected csv, json or human", format)
+            }
+        }
+    }
+}
+
+impl Error for CliError {}
+
+/// The format the accounts are written to standard output in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// CSV, as `export_accounts` writes it.
+    Csv,
+    /// A JSON array, as `export_accounts_json` writes it.
+    Json,
+    /// An aligned table with a totals row, as `print_report` writes it.
+    Human,
+}
+
+impl FromStr for Format {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "human" => Ok(Format::Human),
+            _ => Err(CliError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// The parsed command line.
+struct Args {
+    /// The output format. `--human` is shorthand for `--format human`.
+    format: Format,
+    /// Print throughput and timing to stderr when done.
+    stats: bool,
+    config: EngineConfig,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, CliError> {
+    let mut format = None;
+    let mut stats = false;
+    let mut config = EngineConfig::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--human" if format.is_some() => return Err(CliError::DuplicateOption(arg)),
+            "--human" => format = Some(Format::Human),
+            "--format" if format.is_some() => return Err(CliError::DuplicateOption(arg)),
+            "--format" => match args.next() {
+                Some(value) => format = Some(value.parse()?),
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            "--stats" if stats => return Err(CliError::DuplicateOption(arg)),
+            "--stats" => stats = true,
+            "--retain" if config.retention.is_some() => return Err(CliError::DuplicateOption(arg)),
+            "--retain" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(retention) => config.retention = Some(retention),
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
     }
-    let file_path = &args[1];
-    let file = File::open(file_path)?;
 
-    let mut engine = PaymentEngine::new();
-    engine.process_transactions(file)?;
-    engine.export_accounts(io::stdout())?;
+    if paths.is_empty() {
+        return Err(CliError::MissingPath);
+    }
+    // `-` stands for standard input
+    if let Some(path) = paths.iter().find(|path| path.as_os_str() != "-" && !path.exists()) {
+        return Err(CliError::FileNotFound(path.clone()));
+    }
+    Ok(Args { format: format.unwrap_or(Format::Csv), stats, config, paths })
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    // Files are applied in the order given, so later files may reference earlier ones.
+    let start = Instant::now();
+    let engine = process_files_with_config(&args.paths, args.config)?;
+    let processing = start.elapsed();
+    let counts = engine.counts();
+    if counts.malformed > 0 {
+        eprintln!("Skipped {} malformed rows", counts.malformed);
+    }
+    if counts.unknown_type > 0 {
+        eprintln!("Skipped {} rows with an unknown transaction type", counts.unknown_type);
+    }
+    match args.format {
+        Format::Csv => engine.export_accounts(io::stdout())?,
+        Format::Json => {
+            engine.export_accounts_json(io::stdout())?;
+            println!();
+        }
+        Format::Human => engine.print_report(io::stdout())?,
+    }
 
+    if args.stats {
+        let rows = engine.counts().total();
+        let seconds = processing.as_secs_f64();
+        eprintln!(
+            "Processed {} rows in {:.3}s ({:.0} rows/s), output written in {:.3}s, {:.3}s in total",
+            rows,
+            seconds,
+            rows as f64 / seconds,
+            (start.elapsed() - processing).as_secs_f64(),
+            start.elapsed().as_secs_f64(),
+        );
+    }
     Ok(())
 }
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            if !matches!(err, CliError::FileNotFound(_)) {
+                eprintln!("{}", USAGE);
+            }
+            process::exit(err.exit_code());
+        }
+    };
+
+    if let Err(err) = run(args) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(parse(&["-"]).unwrap().format, Format::Csv);
+        assert_eq!(parse(&["--format", "json", "-"]).unwrap().format, Format::Json);
+        assert_eq!(parse(&["--format", "human", "-"]).unwrap().format, Format::Human);
+        assert_eq!(parse(&["--human", "-"]).unwrap().format, Format::Human);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let err = parse(&["--format", "xml", "-"]).err().unwrap();
+        assert!(matches!(&err, CliError::UnknownFormat(format) if format == "xml"));
+        assert_eq!(err.exit_code(), 5);
+    }
+
+    #[test]
+    fn test_format_without_value() {
+        let err = parse(&["--format"]).err().unwrap();
+        assert!(matches!(&err, CliError::MissingValue(option) if option == "--format"));
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(err.to_string(), "`--format` expects a valid value");
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let err = parse(&["--verbose", "-"]).err().unwrap();
+        assert!(matches!(&err, CliError::UnknownOption(option) if option == "--verbose"));
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(err.to_string(), "unknown option `--verbose`");
+    }
+
+    #[test]
+    fn test_human_with_format() {
+        for args in [["--human", "--format", "csv", "-"], ["--format", "json", "--human", "-"]] {
+            let err = parse(&args).err().unwrap();
+            assert!(matches!(err, CliError::DuplicateOption(_)));
+            assert_eq!(err.exit_code(), 3);
+        }
+    }
+
+    #[test]
+    fn test_retain() {
+        assert_eq!(parse(&["--retain", "10", "-"]).unwrap().config.retention, Some(10));
+
+        let err = parse(&["--retain", "ten", "-"]).err().unwrap();
+        assert!(matches!(&err, CliError::MissingValue(option) if option == "--retain"));
+
+        let err = parse(&["--retain", "10", "--retain", "20", "-"]).err().unwrap();
+        assert!(matches!(&err, CliError::DuplicateOption(option) if option == "--retain"));
+        assert_eq!(err.to_string(), "`--retain` sets an option that was already given");
+    }
+}
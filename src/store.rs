@@ -0,0 +1,105 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Keyed storage for the engine's accounts or stored transactions.
+///
+/// The engine only gets, inserts, removes and iterates entries, so a backend such as an
+/// on-disk key-value store can be swapped in for the in-memory `HashMap` when the data does
+/// not fit in memory. Handlers update entries in place through `get_mut`; a backend that does
+/// not keep values in memory should cache the entries it hands out and write them back later.
+pub trait Store<K, V>: IntoIterator<Item = (K, V)> {
+    /// Returns the value stored under `key`, if any.
+    fn get(&self, key: &K) -> Option<&V>;
+
+    /// Returns the value stored under `key` for updating in place, if any.
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+
+    /// Returns the value stored under `key`, first inserting the result of `default` if there
+    /// is none.
+    fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V;
+
+    /// Stores `value` under `key`, returning the value it replaced, if any.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    /// Removes and returns the value stored under `key`, if any.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// Returns the number of stored entries.
+    fn len(&self) -> usize;
+
+    /// Iterates over all entries, in no particular order.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a K, &'a V)>
+    where
+        K: 'a,
+        V: 'a;
+
+    /// Returns whether a value is stored under `key`.
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns whether the store is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over all keys, in no particular order.
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// Iterates over all values, in no particular order.
+    fn values<'a>(&'a self) -> impl Iterator<Item = &'a V>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<K: Eq + Hash, V> Store<K, V> for HashMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        match self.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        HashMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        HashMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a K, &'a V)>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        HashMap::iter(self)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        HashMap::contains_key(self, key)
+    }
+}
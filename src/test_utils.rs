@@ -0,0 +1,63 @@
+use crate::engine::{format_amount, ClientId, OutputAccount, PaymentEngine};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Asserts that the engine's accounts match an expected CSV export.
+///
+/// Both sides are parsed into `OutputAccount`s, so amounts are compared by value rather than
+/// by formatting, e.g. `1.5` matches `1.5000`, and rows may come in any order. Whitespace
+/// around fields and any `reference` column are ignored. The engine's side is rounded like
+/// its exports.
+///
+/// Panics if `expected_csv` cannot be parsed, or with a message listing every client whose
+/// account differs, is missing or is unexpected.
+pub fn assert_accounts_match(engine: &PaymentEngine, expected_csv: &str) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(expected_csv.as_bytes());
+    let mut expected = BTreeMap::new();
+    for (index, row) in reader.deserialize::<OutputAccount>().enumerate() {
+        let mut account = row.unwrap_or_else(|err| panic!("expected row {} is not an account: {}", index + 1, err));
+        account.reference = None;
+        expected.insert(account.id, account);
+    }
+
+    let value = engine.accounts_to_json_value().expect("engine accounts should export");
+    let actual: Vec<OutputAccount> = serde_json::from_value(value).expect("exported accounts always parse back");
+    let mut actual: BTreeMap<ClientId, OutputAccount> =
+        actual.into_iter().map(|account| (account.id, account)).collect();
+
+    let mut mismatches = String::new();
+    for (client, expected) in &expected {
+        match actual.remove(client) {
+            Some(actual) if actual == *expected => {}
+            Some(actual) => {
+                let _ = writeln!(
+                    mismatches,
+                    "client {}: expected {}, got {}",
+                    client,
+                    describe(expected),
+                    describe(&actual)
+                );
+            }
+            None => {
+                let _ = writeln!(mismatches, "client {}: expected {}, got no account", client, describe(expected));
+            }
+        }
+    }
+    for (client, actual) in &actual {
+        let _ = writeln!(mismatches, "client {}: unexpected account {}", client, describe(actual));
+    }
+    assert!(mismatches.is_empty(), "accounts do not match:\n{}", mismatches);
+}
+
+/// Describes an account's balances for a mismatch message.
+fn describe(account: &OutputAccount) -> String {
+    format!(
+        "available {}, held {}, total {}, {}",
+        format_amount(account.available),
+        format_amount(account.held),
+        format_amount(account.total),
+        if account.locked { "locked" } else { "unlocked" }
+    )
+}
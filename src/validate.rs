@@ -0,0 +1,153 @@
+use crate::engine::{ClientId, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// The columns every transaction input must have, in any order.
+const COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Summary of a structurally valid CSV input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvStats {
+    /// The number of data rows, excluding the header.
+    pub rows: u64,
+}
+
+/// What is structurally wrong with a CSV input.
+#[derive(Debug)]
+pub enum CsvErrorKind {
+    /// The header row lacks a required column.
+    MissingColumn(&'static str),
+    /// A row has the wrong number of fields.
+    FieldCount { expected: usize, found: usize },
+    /// A field could not be parsed as its column's type.
+    InvalidField { column: &'static str, value: String },
+    /// A deposit, withdrawal or adjustment has no amount.
+    MissingAmount,
+    /// The input could not be read as CSV.
+    Csv(csv::Error),
+}
+
+/// A structural error in a CSV input and the line it occurred on.
+#[derive(Debug)]
+pub struct CsvError {
+    /// The 1-based line number of the offending row.
+    pub line: u64,
+    /// What was wrong with it.
+    pub kind: CsvErrorKind,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            CsvErrorKind::MissingColumn(column) => {
+                write!(f, "line {}: input is missing the required `{}` column", self.line, column)
+            }
+            CsvErrorKind::FieldCount { expected, found } => {
+                write!(f, "line {}: expected {} fields, found {}", self.line, expected, found)
+            }
+            CsvErrorKind::InvalidField { column, value } => {
+                write!(f, "line {}: invalid `{}` value `{}`", self.line, column, value)
+            }
+            CsvErrorKind::MissingAmount => write!(f, "line {}: amount is required", self.line),
+            CsvErrorKind::Csv(err) => write!(f, "line {}: {}", self.line, err),
+        }
+    }
+}
+
+impl Error for CsvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            CsvErrorKind::Csv(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that a CSV input is well-formed without applying any transactions.
+///
+/// The header must name the `type`, `client`, `tx` and `amount` columns, every row must have
+/// exactly that many fields, and each field must parse as its column's type. Amounts are
+/// required for deposits, withdrawals and adjustments. Semantic rules such as sufficient
+/// funds or valid dispute targets are not checked. All errors found are returned.
+pub fn validate_csv_structure<R: io::Read>(reader: R) -> Result<CsvStats, Vec<CsvError>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(reader);
+    let headers = rdr
+        .headers()
+        .map_err(|err| vec![CsvError { line: 1, kind: CsvErrorKind::Csv(err) }])?
+        .clone();
+
+    let mut positions = [0; COLUMNS.len()];
+    let mut errors = Vec::new();
+    for (position, column) in positions.iter_mut().zip(COLUMNS) {
+        match headers.iter().position(|header| header == column) {
+            Some(index) => *position = index,
+            None => errors.push(CsvError { line: 1, kind: CsvErrorKind::MissingColumn(column) }),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let [type_index, client_index, tx_index, amount_index] = positions;
+
+    let mut stats = CsvStats { rows: 0 };
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map_or(0, |pos| pos.line());
+                errors.push(CsvError { line, kind: CsvErrorKind::Csv(err) });
+                continue;
+            }
+        };
+        stats.rows += 1;
+        let line = record.position().map_or(0, |pos| pos.line());
+        if record.len() != headers.len() {
+            errors.push(CsvError {
+                line,
+                kind: CsvErrorKind::FieldCount { expected: headers.len(), found: record.len() },
+            });
+            continue;
+        }
+
+        let transaction_type = record[type_index].parse::<TransactionType>().ok();
+        if transaction_type.is_none() {
+            errors.push(invalid_field(line, "type", &record[type_index]));
+        }
+        if record[client_index].parse::<ClientId>().is_err() {
+            errors.push(invalid_field(line, "client", &record[client_index]));
+        }
+        if record[tx_index].parse::<TransactionId>().is_err() {
+            errors.push(invalid_field(line, "tx", &record[tx_index]));
+        }
+
+        let amount = &record[amount_index];
+        if amount.is_empty() {
+            if matches!(
+                transaction_type,
+                Some(TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Adjustment)
+            ) {
+                errors.push(CsvError { line, kind: CsvErrorKind::MissingAmount });
+            }
+        } else if Decimal::from_str(amount).is_err() {
+            errors.push(invalid_field(line, "amount", amount));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(stats)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Builds the error for a field that does not parse as its column's type.
+fn invalid_field(line: u64, column: &'static str, value: &str) -> CsvError {
+    CsvError { line, kind: CsvErrorKind::InvalidField { column, value: value.to_string() } }
+}
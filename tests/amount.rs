@@ -0,0 +1,132 @@
+use rs_accountant::amount::Amount;
+use rs_accountant::engine::*;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// A fixed-point amount in whole cents, as an integer backend would keep it.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(try_from = "String")]
+struct Cents(i64);
+
+impl TryFrom<String> for Cents {
+    type Error = rust_decimal::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(Cents::from_decimal(value.parse()?))
+    }
+}
+
+impl Add for Cents {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Cents(self.0 + other.0)
+    }
+}
+
+impl Sub for Cents {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Cents(self.0 - other.0)
+    }
+}
+
+impl Neg for Cents {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Cents(-self.0)
+    }
+}
+
+impl AddAssign for Cents {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Cents {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Cents {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        Cents(iter.map(|cents| cents.0).sum())
+    }
+}
+
+impl Amount for Cents {
+    const ZERO: Self = Cents(0);
+
+    fn abs(self) -> Self {
+        Cents(self.0.abs())
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Cents)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Cents)
+    }
+
+    fn to_decimal(self) -> Decimal {
+        Decimal::new(self.0, 2)
+    }
+
+    fn from_decimal(value: Decimal) -> Self {
+        Cents((value * Decimal::ONE_HUNDRED).round().to_i64().unwrap())
+    }
+}
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,10.50\n\
+                     deposit,2,2,3.25\n\
+                     withdrawal,1,3,4.10\n\
+                     withdrawal,2,4,5.00\n\
+                     deposit,2,5,1.99\n\
+                     dispute,2,5,\n\
+                     adjustment,1,6,-0.40\n\
+                     deposit,3,7,7.00\n\
+                     dispute,3,7,\n\
+                     chargeback,3,7,\n";
+
+#[test]
+fn test_integer_backend_matches_decimal() {
+    let mut decimal_engine = PaymentEngine::new();
+    decimal_engine.process_transactions(INPUT.as_bytes()).unwrap();
+    let mut cents_engine = PaymentEngine::<Cents>::default();
+    cents_engine.process_transactions(INPUT.as_bytes()).unwrap();
+
+    assert_eq!(cents_engine.accounts.get(&1).unwrap().available, Cents(600));
+    assert_eq!(cents_engine.accounts.get(&2).unwrap().held, Cents(199));
+    assert_eq!(cents_engine.disputed_exposure(2), Cents(199));
+
+    let mut decimal_output = Vec::new();
+    decimal_engine.export_accounts(&mut decimal_output).unwrap();
+    let mut cents_output = Vec::new();
+    cents_engine.export_accounts(&mut cents_output).unwrap();
+    assert_eq!(String::from_utf8(cents_output).unwrap(), String::from_utf8(decimal_output).unwrap());
+}
+
+#[test]
+fn test_integer_backend_rounds_fees() {
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule {
+            flat: Decimal::ZERO,
+            rate: Decimal::new(1, 2),
+        }),
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::<Cents>::with_amount_config(config);
+    engine
+        .process_transactions("type,client,tx,amount\ndeposit,1,1,2.50\n".as_bytes())
+        .unwrap();
+
+    // A 1% fee on 2.50 is 0.025, which rounds to 0.02 in whole cents
+    assert_eq!(engine.fees_collected(), Cents(2));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, Cents(248));
+}
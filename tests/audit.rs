@@ -0,0 +1,176 @@
+use rs_accountant::audit::{self, AuditEntry};
+use rs_accountant::engine::*;
+use rust_decimal_macros::dec;
+
+const INPUT: &str = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     withdrawal,1,2,500\n\
+                     withdrawal,1,3,30\n\
+                     dispute,1,1,\n";
+
+fn audited_engine() -> PaymentEngine {
+    let config = EngineConfig {
+        audit: true,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(INPUT.as_bytes()).unwrap();
+    engine
+}
+
+#[test]
+fn test_audit_records_applied_transactions() {
+    let engine = audited_engine();
+    let entries = engine.audit_entries();
+
+    // The rejected withdrawal is not recorded
+    assert_eq!(entries.len(), 3);
+    assert_eq!(
+        entries[0],
+        AuditEntry {
+            tx_id: 1,
+            client_id: 1,
+            transaction_type: TransactionType::Deposit,
+            amount: Some(dec!(100.0)),
+            available_before: dec!(0),
+            held_before: dec!(0),
+            available_after: dec!(100.0),
+            held_after: dec!(0),
+        }
+    );
+    assert_eq!(entries[1].tx_id, 3);
+    assert_eq!(entries[2].transaction_type, TransactionType::Dispute);
+    assert_eq!(entries[2].available_before, dec!(70.0));
+    assert_eq!(entries[2].available_after, dec!(-30.0));
+    assert_eq!(entries[2].held_after, dec!(100.0));
+}
+
+#[test]
+fn test_audit_disabled_by_default() {
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(INPUT.as_bytes()).unwrap();
+    assert!(engine.audit_entries().is_empty());
+}
+
+#[test]
+fn test_audit_csv_dump() {
+    let engine = audited_engine();
+    let mut output = Vec::new();
+    audit::write_csv(engine.audit_entries(), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "tx,client,type,amount,available_before,held_before,available_after,held_after"
+    );
+    assert_eq!(lines.next().unwrap(), "1,1,deposit,100,0,0,100,0");
+    assert_eq!(lines.count(), 2);
+}
+
+#[test]
+fn test_audit_json_dump() {
+    let engine = audited_engine();
+    let mut output = Vec::new();
+    audit::write_json(engine.audit_entries(), &mut output).unwrap();
+
+    let parsed: Vec<AuditEntry> = serde_json::from_slice(&output).unwrap();
+    assert_eq!(parsed, engine.audit_entries());
+}
+
+#[test]
+fn test_balance_at() {
+    let config = EngineConfig { audit: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,2,2,7\n\
+                 withdrawal,1,3,30\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n\
+                 adjustment,1,4,-5\n\
+                 withdrawal,1,5,1000\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // The later dispute of tx 1 does not change the balance recorded for it
+    assert_eq!(engine.balance_at(1, 1), Some(dec!(100)));
+    assert_eq!(engine.balance_at(1, 3), Some(dec!(70)));
+    assert_eq!(engine.balance_at(1, 4), Some(dec!(65)));
+    assert_eq!(engine.balance_at(2, 2), Some(dec!(7)));
+    // Rejected transactions and other clients' transactions have no balance
+    assert_eq!(engine.balance_at(1, 5), None);
+    assert_eq!(engine.balance_at(2, 1), None);
+
+    // Without auditing there is no history to reconstruct from
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.balance_at(1, 1), None);
+}
+
+#[test]
+fn test_replay_audit() {
+    let config = EngineConfig { audit: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,2,2,7\n\
+                 withdrawal,1,3,30\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,40\n\
+                 adjustment,2,4,-5\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let replayed = PaymentEngine::replay_audit(engine.audit_entries()).unwrap();
+    assert!(engine.diff(&replayed).is_empty());
+    // The rebuilt engine's own log replays in turn
+    assert_eq!(replayed.audit_entries(), engine.audit_entries());
+}
+
+#[test]
+fn test_replay_audit_detects_inconsistencies() {
+    let engine = audited_engine();
+
+    // A missing entry shows up as a mismatched starting balance
+    let mut entries = engine.audit_entries().to_vec();
+    entries.remove(0);
+    let Err(err) = PaymentEngine::replay_audit(&entries) else { panic!("replay succeeded") };
+    assert_eq!(err.index, 0);
+    assert_eq!(err.tx_id, 3);
+    assert_eq!(
+        err.kind,
+        ReplayErrorKind::BalanceBeforeMismatch { logged: (dec!(100), dec!(0)), replayed: (dec!(0), dec!(0)) }
+    );
+
+    // A tampered result is caught at the entry itself
+    let mut entries = engine.audit_entries().to_vec();
+    entries[1].available_after = dec!(80);
+    let Err(err) = PaymentEngine::replay_audit(&entries) else { panic!("replay succeeded") };
+    assert_eq!((err.index, err.tx_id), (1, 3));
+    assert_eq!(
+        err.kind,
+        ReplayErrorKind::BalanceAfterMismatch { logged: (dec!(80), dec!(0)), replayed: (dec!(70), dec!(0)) }
+    );
+
+    // An entry the engine would not apply is rejected
+    let mut entries = engine.audit_entries().to_vec();
+    entries[1].amount = Some(dec!(1000));
+    let Err(err) = PaymentEngine::replay_audit(&entries) else { panic!("replay succeeded") };
+    assert_eq!(err.kind, ReplayErrorKind::Rejected(RejectReason::InsufficientFunds));
+}
+
+#[test]
+fn test_replay_audit_misses_unlogged_changes() {
+    let config = EngineConfig { audit: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,1,100\n".as_bytes()).unwrap();
+    engine.accrue_interest(dec!(0.1));
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,2,5\n".as_bytes()).unwrap();
+
+    // The interest is not logged, so the next entry starts from a different balance
+    let Err(err) = PaymentEngine::replay_audit(engine.audit_entries()) else { panic!("replay succeeded") };
+    assert_eq!((err.index, err.tx_id), (1, 2));
+    assert_eq!(
+        err.kind,
+        ReplayErrorKind::BalanceBeforeMismatch { logged: (dec!(110), dec!(0)), replayed: (dec!(100), dec!(0)) }
+    );
+}
@@ -0,0 +1,75 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::binary::{self, Decoded, RECORD_LEN};
+use rs_accountant::engine::*;
+use rs_accountant::generator;
+use rust_decimal_macros::dec;
+
+#[test]
+fn test_binary_input_matches_csv() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let (transactions, reference) = generator::generate(&mut rng, 50, 1000);
+
+    let mut input = Vec::new();
+    generator::write_transactions_binary(&transactions, &mut input).unwrap();
+    assert_eq!(input.len(), transactions.len() * RECORD_LEN);
+
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions_binary(input.as_slice()).unwrap();
+    assert!(engine.diff(&reference).is_empty());
+    assert_eq!(engine.counts().malformed, 0);
+}
+
+#[test]
+fn test_binary_record_encoding() {
+    let tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 0x0102,
+        tx_id: 0x0304_0506,
+        amount: Some(dec!(-12.3456)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let record = binary::encode(&tx);
+    assert_eq!(record[..7], [1, 0x02, 0x01, 0x06, 0x05, 0x04, 0x03]);
+    let Decoded::Transaction(decoded) = binary::decode(&record) else {
+        panic!("expected a transaction");
+    };
+    assert_eq!(decoded.transaction_type, TransactionType::Withdrawal);
+    assert_eq!((decoded.client_id, decoded.tx_id), (0x0102, 0x0304_0506));
+    assert_eq!(decoded.amount, Some(dec!(-12.3456)));
+
+    // An all-zero amount means the transaction has none
+    let dispute = InputTransaction { transaction_type: TransactionType::Dispute, amount: None, ..tx };
+    let record = binary::encode(&dispute);
+    assert_eq!(record[7..], [0; 16]);
+    let Decoded::Transaction(decoded) = binary::decode::<rust_decimal::Decimal>(&record) else {
+        panic!("expected a transaction");
+    };
+    assert_eq!(decoded.amount, None);
+}
+
+#[test]
+fn test_binary_unknown_type_and_truncated_record() {
+    let deposit = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(10)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let mut input = binary::encode(&deposit).to_vec();
+    let mut unknown = binary::encode(&InputTransaction { tx_id: 2, ..deposit.clone() });
+    unknown[0] = 200;
+    input.extend_from_slice(&unknown);
+    input.extend_from_slice(&binary::encode(&InputTransaction { tx_id: 3, ..deposit })[..10]);
+
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions_binary(input.as_slice()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(10));
+    assert_eq!(engine.counts().unknown_type, 1);
+    assert_eq!(engine.counts().malformed, 1);
+}
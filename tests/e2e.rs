@@ -0,0 +1,68 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::engine::{process_sharded_dir, InputTransaction, PaymentEngine};
+use rs_accountant::generator;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+#[test]
+fn test_generated_input_round_trips() {
+    for seed in 0..5 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (transactions, reference) = generator::generate(&mut rng, 50, 1000);
+
+        let mut input = Vec::new();
+        generator::write_transactions(&transactions, &mut input).unwrap();
+        let mut expected = Vec::new();
+        reference.export_accounts(&mut expected).unwrap();
+
+        let mut engine = PaymentEngine::new();
+        engine.process_transactions(input.as_slice()).unwrap();
+        let mut actual = Vec::new();
+        engine.export_accounts(&mut actual).unwrap();
+
+        assert_eq!(engine.counts().malformed, 0, "seed {}", seed);
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            String::from_utf8(expected).unwrap(),
+            "seed {}",
+            seed
+        );
+    }
+}
+
+/// Splits transactions into `shards` client-disjoint files in a fresh temporary directory.
+fn write_shards(transactions: &[InputTransaction], shards: u16) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rs-accountant-shards-{}-{}", std::process::id(), shards));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for shard in 0..shards {
+        let shard_transactions: Vec<_> = transactions
+            .iter()
+            .filter(|tx| tx.client_id % shards == shard)
+            .cloned()
+            .collect();
+        let file = File::create(dir.join(format!("client_{}.csv", shard))).unwrap();
+        generator::write_transactions(&shard_transactions, file).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_output_independent_of_shard_count() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let (transactions, _) = generator::generate(&mut rng, 50, 5000);
+
+    let mut outputs = Vec::new();
+    for shards in [1, 8] {
+        let dir = write_shards(&transactions, shards);
+        let engine = process_sharded_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(engine.counts().total(), 5000, "{} shards", shards);
+        let mut output = Vec::new();
+        engine.export_accounts(&mut output).unwrap();
+        outputs.push(output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+}
@@ -0,0 +1,40 @@
+use rs_accountant::engine::*;
+use rs_accountant::generator::TransactionWriter;
+use rust_decimal_macros::dec;
+
+#[test]
+fn test_written_types_round_trip() {
+    let types = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Reversal,
+        TransactionType::Adjustment,
+    ];
+    let mut output = Vec::new();
+    let mut wtr = TransactionWriter::new(&mut output);
+    for (tx_id, &transaction_type) in (1..).zip(&types) {
+        let tx = InputTransaction {
+            transaction_type,
+            client_id: 1,
+            tx_id,
+            amount: Some(dec!(1.5)),
+            currency: None,
+            reference: None,
+            seq: None,
+        };
+        wtr.write(&tx).unwrap();
+    }
+    wtr.flush().unwrap();
+    drop(wtr);
+
+    let output = String::from_utf8(output).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(lines.next().unwrap(), "type,client,tx,amount");
+    for (line, expected) in lines.zip(types) {
+        let written = line.split(',').next().unwrap();
+        assert_eq!(written.parse::<TransactionType>(), Ok(expected), "`{}` does not round-trip", written);
+    }
+}
@@ -0,0 +1,44 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::engine::*;
+use rs_accountant::generator;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+#[test]
+fn test_random_streams_keep_account_invariants() {
+    for seed in 0..50 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (transactions, _) = generator::generate(&mut rng, 20, 2000);
+        let config = EngineConfig { held_check: Some(HeldCheck::Panic), ..Default::default() };
+        let mut engine = PaymentEngine::with_config(config);
+        for tx in transactions {
+            let _ = engine.apply(tx);
+        }
+
+        let mut disputed: HashMap<ClientId, Decimal> = HashMap::new();
+        for tx in engine.transactions.values() {
+            if tx.dispute_status == DisputeStatus::Disputed {
+                *disputed.entry(tx.client_id).or_default() += tx.held;
+            }
+        }
+
+        for (client_id, account) in &engine.accounts {
+            assert!(account.held >= Decimal::ZERO, "seed {}, client {}", seed, client_id);
+            assert_eq!(
+                account.checked_total(),
+                Some(account.available + account.held),
+                "seed {}, client {}",
+                seed,
+                client_id
+            );
+            assert_eq!(
+                account.held,
+                disputed.get(client_id).copied().unwrap_or_default(),
+                "seed {}, client {}",
+                seed,
+                client_id
+            );
+        }
+    }
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "metrics")]
+
+use rs_accountant::engine::*;
+use rust_decimal_macros::dec;
+
+#[test]
+fn test_amount_histogram() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,0.5\n\
+                 deposit,1,2,0.05\n\
+                 deposit,1,3,1\n\
+                 deposit,1,4,1.0001\n\
+                 deposit,1,5,99.99\n\
+                 deposit,1,6,0.9\n\
+                 withdrawal,1,7,10\n\
+                 withdrawal,1,8,1000\n\
+                 dispute,1,1,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let counts = engine.counts();
+    assert_eq!(
+        counts.deposit_amounts.buckets(),
+        vec![(dec!(0.1), 1), (dec!(1), 3), (dec!(10), 1), (dec!(100), 1)]
+    );
+    // The second withdrawal is rejected and not counted
+    assert_eq!(counts.withdrawal_amounts.buckets(), vec![(dec!(10), 1)]);
+}
+
+#[test]
+fn test_amount_histogram_merge() {
+    let mut first = PaymentEngine::new();
+    first.process_transactions("type,client,tx,amount\ndeposit,1,1,5\n".as_bytes()).unwrap();
+    let mut second = PaymentEngine::new();
+    second.process_transactions("type,client,tx,amount\ndeposit,2,2,7\ndeposit,2,3,70\n".as_bytes()).unwrap();
+
+    first.merge(second).unwrap();
+    assert_eq!(first.counts().deposit_amounts.buckets(), vec![(dec!(10), 2), (dec!(100), 1)]);
+}
@@ -1,5 +1,9 @@
 use rs_accountant::engine::*;
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use std::collections::HashSet;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
 
 #[test]
 fn test_deposit() {
@@ -9,8 +13,11 @@ fn test_deposit() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(tx);
+    engine.handle_deposit(tx).unwrap();
     
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(100.0));
@@ -30,16 +37,22 @@ fn test_withdrawal_success() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     let withdrawal_tx = InputTransaction {
         transaction_type: TransactionType::Withdrawal,
         client_id: 1,
         tx_id: 2,
         amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_withdrawal(withdrawal_tx);
+    engine.handle_withdrawal(withdrawal_tx).unwrap();
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(50.0));
@@ -54,16 +67,22 @@ fn test_withdrawal_insufficient_funds() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     let withdrawal_tx = InputTransaction {
         transaction_type: TransactionType::Withdrawal,
         client_id: 1,
         tx_id: 2,
         amount: Some(dec!(150.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_withdrawal(withdrawal_tx);
+    assert_eq!(engine.handle_withdrawal(withdrawal_tx), Err(RejectReason::InsufficientFunds));
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(100.0)); // Unchanged
@@ -77,16 +96,22 @@ fn test_dispute_resolve_cycle() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     let dispute_tx = InputTransaction {
         transaction_type: TransactionType::Dispute,
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx);
+    engine.handle_dispute(dispute_tx).unwrap();
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(0.0));
@@ -99,8 +124,11 @@ fn test_dispute_resolve_cycle() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_resolve(resolve_tx);
+    engine.handle_resolve(resolve_tx).unwrap();
     
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(100.0));
@@ -117,16 +145,22 @@ fn test_dispute_chargeback_cycle() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     let dispute_tx = InputTransaction {
         transaction_type: TransactionType::Dispute,
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx);
+    engine.handle_dispute(dispute_tx).unwrap();
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.held, dec!(100.0));
@@ -136,8 +170,11 @@ fn test_dispute_chargeback_cycle() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_chargeback(chargeback_tx);
+    engine.handle_chargeback(chargeback_tx).unwrap();
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(0.0));
@@ -150,15 +187,18 @@ fn test_dispute_chargeback_cycle() {
 #[test]
 fn test_locked_account_withdrawal() {
     let mut engine = PaymentEngine::new();
-    engine.accounts.insert(1, Account { id: 1, available: dec!(100.0), held: dec!(0.0), locked: true });
+    engine.accounts.insert(1, Account { id: 1, available: dec!(100.0), held: dec!(0.0), locked: true, currency: None, pending_fees: dec!(0), last_reference: None, disputes: 0 });
 
     let withdrawal_tx = InputTransaction {
         transaction_type: TransactionType::Withdrawal,
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_withdrawal(withdrawal_tx);
+    assert_eq!(engine.handle_withdrawal(withdrawal_tx), Err(RejectReason::AccountLocked));
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(100.0)); // Unchanged
@@ -167,15 +207,18 @@ fn test_locked_account_withdrawal() {
 #[test]
 fn test_locked_account_deposit() {
     let mut engine = PaymentEngine::new();
-    engine.accounts.insert(1, Account { id: 1, available: dec!(100.0), held: dec!(0.0), locked: true });
+    engine.accounts.insert(1, Account { id: 1, available: dec!(100.0), held: dec!(0.0), locked: true, currency: None, pending_fees: dec!(0), last_reference: None, disputes: 0 });
 
     let deposit_tx = InputTransaction {
         transaction_type: TransactionType::Deposit,
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    assert_eq!(engine.handle_deposit(deposit_tx), Err(RejectReason::AccountLocked));
 
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(100.0)); // Unchanged, since deposits are blocked to locked accounts
@@ -191,8 +234,11 @@ fn test_re_dispute_resolved_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     // Dispute it
     let dispute_tx = InputTransaction {
@@ -200,8 +246,11 @@ fn test_re_dispute_resolved_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx);
+    engine.handle_dispute(dispute_tx).unwrap();
     
     // Resolve it
     let resolve_tx = InputTransaction {
@@ -209,8 +258,11 @@ fn test_re_dispute_resolved_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_resolve(resolve_tx);
+    engine.handle_resolve(resolve_tx).unwrap();
     
     // Verify it's resolved
     assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Resolved);
@@ -224,8 +276,11 @@ fn test_re_dispute_resolved_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx2);
+    engine.handle_dispute(dispute_tx2).unwrap();
     
     // Verify the re-dispute worked
     assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Disputed);
@@ -244,8 +299,11 @@ fn test_cannot_dispute_charged_back_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
 
     // Dispute it
     let dispute_tx = InputTransaction {
@@ -253,8 +311,11 @@ fn test_cannot_dispute_charged_back_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx);
+    engine.handle_dispute(dispute_tx).unwrap();
     
     // Chargeback
     let chargeback_tx = InputTransaction {
@@ -262,8 +323,11 @@ fn test_cannot_dispute_charged_back_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_chargeback(chargeback_tx);
+    engine.handle_chargeback(chargeback_tx).unwrap();
     
     // Verify it's charged back and account is locked
     assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::ChargedBack);
@@ -278,8 +342,11 @@ fn test_cannot_dispute_charged_back_transaction() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx2);
+    assert_eq!(engine.handle_dispute(dispute_tx2), Err(RejectReason::AccountLocked));
     
     // Verify the dispute was blocked - status should remain ChargedBack
     assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::ChargedBack);
@@ -299,8 +366,11 @@ fn test_dispute_with_insufficient_funds_creates_negative_balance() {
         client_id: 1,
         tx_id: 1,
         amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_deposit(deposit_tx);
+    engine.handle_deposit(deposit_tx).unwrap();
     
     // Withdraw $80, leaving $20 available
     let withdrawal_tx = InputTransaction {
@@ -308,14 +378,19 @@ fn test_dispute_with_insufficient_funds_creates_negative_balance() {
         client_id: 1,
         tx_id: 2,
         amount: Some(dec!(80.0)),
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_withdrawal(withdrawal_tx);
+    engine.handle_withdrawal(withdrawal_tx).unwrap();
     
     // Verify account state before dispute
     let account = engine.accounts.get(&1).unwrap();
     assert_eq!(account.available, dec!(20.0));
     assert_eq!(account.held, dec!(0.0));
     assert_eq!(account.total(), dec!(20.0));
+    assert!(!account.is_overdrawn());
+    assert!(engine.overdrawn_clients().is_empty());
     
     // Now dispute the original $100 deposit - this should be allowed even though
     // we only have $20 available, creating a negative balance
@@ -324,8 +399,11 @@ fn test_dispute_with_insufficient_funds_creates_negative_balance() {
         client_id: 1,
         tx_id: 1,
         amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
     };
-    engine.handle_dispute(dispute_tx);
+    engine.handle_dispute(dispute_tx).unwrap();
     
     // Verify the dispute created a negative available balance
     let account = engine.accounts.get(&1).unwrap();
@@ -333,4 +411,3207 @@ fn test_dispute_with_insufficient_funds_creates_negative_balance() {
     assert_eq!(account.held, dec!(100.0));
     assert_eq!(account.total(), dec!(20.0)); // total should still be correct
     assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Disputed);
-} 
\ No newline at end of file
+    assert!(account.is_overdrawn());
+    assert_eq!(engine.overdrawn_clients(), [1]);
+} 
+#[test]
+fn test_disputed_exposure() {
+    let mut engine = PaymentEngine::new();
+    for (tx_id, amount) in [(1, dec!(100.0)), (2, dec!(50.0))] {
+        engine.handle_deposit(InputTransaction {
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id,
+            amount: Some(amount),
+            currency: None,
+            reference: None,
+            seq: None,
+        }).unwrap();
+    }
+    assert_eq!(engine.disputed_exposure(1), dec!(0));
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_dispute(dispute_tx).unwrap();
+    assert_eq!(engine.disputed_exposure(1), dec!(100.0));
+    assert_eq!(engine.disputed_exposure(1), engine.accounts.get(&1).unwrap().held);
+
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_resolve(resolve_tx).unwrap();
+    assert_eq!(engine.disputed_exposure(1), dec!(0));
+
+    // Unknown clients have no exposure
+    assert_eq!(engine.disputed_exposure(2), dec!(0));
+}
+
+#[test]
+fn test_print_report() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,1000.00\n\
+                 deposit,1,2,2.5\n\
+                 withdrawal,2,3,500.00\n\
+                 dispute,2,1,\n\
+                 chargeback,2,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let mut output = Vec::new();
+    engine.print_report(&mut output).unwrap();
+    let expected = "\
+client  available    held      total  locked
+     1     2.5000  0.0000     2.5000   false
+     2  -500.0000  0.0000  -500.0000    true
+ total  -497.5000  0.0000  -497.5000
+";
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn test_format_amount() {
+    assert_eq!(format_amount(dec!(100)), "100.0000");
+    assert_eq!(format_amount(dec!(100.1)), "100.1000");
+    assert_eq!(format_amount(dec!(100.10000)), "100.1000");
+    assert_eq!(format_amount(dec!(100.12345)), "100.12345");
+    assert_eq!(format_amount(dec!(0)), "0.0000");
+    assert_eq!(format_amount(dec!(-100)), "-100.0000");
+    assert_eq!(format_amount(dec!(-100.12345)), "-100.12345");
+}
+
+#[test]
+fn test_withdrawal_of_exact_balance() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(1.5)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(1.5000)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_withdrawal(withdrawal_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0));
+    assert!(engine.transactions.contains_key(&2));
+}
+
+#[test]
+fn test_withdrawal_of_balance_plus_smallest_decimal() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(1.5)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    // The smallest representable step above the balance must still be rejected
+    let amount = dec!(1.5) + Decimal::new(1, 28);
+    assert!(amount > dec!(1.5));
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(amount),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_withdrawal(withdrawal_tx), Err(RejectReason::InsufficientFunds));
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(1.5)); // Unchanged
+    assert!(!engine.transactions.contains_key(&2));
+}
+
+#[test]
+fn test_deposit_fee() {
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule { flat: dec!(0), rate: dec!(0.01) }),
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.00)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(99.00));
+    let stored_tx = engine.transactions.get(&1).unwrap();
+    assert_eq!(stored_tx.amount, dec!(99.00));
+    assert_eq!(stored_tx.fee, dec!(1.00));
+    assert_eq!(engine.fees_collected(), dec!(1.00));
+}
+
+#[test]
+fn test_fees_accumulate() {
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule { flat: dec!(0.10), rate: dec!(0.01) }),
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.00)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(50.00)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_withdrawal(withdrawal_tx).unwrap();
+
+    // Deposit fee of 1.10, withdrawal fee of 0.60
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(48.30));
+    assert_eq!(engine.fees_collected(), dec!(1.70));
+
+    // A withdrawal that can't cover its fee is rejected and charges nothing
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(48.30)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_withdrawal(withdrawal_tx), Err(RejectReason::InsufficientFunds));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(48.30));
+    assert_eq!(engine.fees_collected(), dec!(1.70));
+}
+
+#[test]
+fn test_accrued_fees() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,50\n";
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule { flat: dec!(0.10), rate: dec!(0.01) }),
+        accrue_fees: true,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config.clone());
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Deposit fee of 1.10 and withdrawal fee of 0.60 are accrued, not deducted
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(50));
+    assert_eq!(account.pending_fees, dec!(1.70));
+    assert_eq!(account.total(), dec!(50));
+    assert_eq!(account.checked_total_with(TotalMode::NetOfPendingFees), Some(dec!(48.30)));
+    assert_eq!(engine.fees_collected(), dec!(0));
+
+    // The gross total is exported by default
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,50.0000,0.0000,50.0000,false\n");
+
+    let mut engine = PaymentEngine::with_config(EngineConfig { total_mode: TotalMode::NetOfPendingFees, ..config });
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,50.0000,0.0000,48.3000,false\n");
+}
+
+#[test]
+fn test_print_report_net_of_pending_fees() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,50\n";
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule { flat: dec!(0.10), rate: dec!(0.01) }),
+        accrue_fees: true,
+        total_mode: TotalMode::NetOfPendingFees,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // The totals row sums the account totals rather than available plus held
+    let mut output = Vec::new();
+    engine.print_report(&mut output).unwrap();
+    let expected = "\
+client  available    held    total  locked
+     1    50.0000  0.0000  48.3000   false
+ total    50.0000  0.0000  48.3000
+";
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn test_net_total_without_pending_fees() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n";
+    let config = EngineConfig {
+        fee_schedule: Some(FeeSchedule { flat: dec!(1), rate: dec!(0) }),
+        total_mode: TotalMode::NetOfPendingFees,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Fees collected immediately leave nothing pending, so the totals agree
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.pending_fees, dec!(0));
+    assert_eq!(account.checked_total_with(TotalMode::NetOfPendingFees), Some(account.total()));
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,99.0000,0.0000,99.0000,false\n");
+}
+
+#[test]
+fn test_default_config_charges_no_fees() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.00)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.00));
+    assert_eq!(engine.transactions.get(&1).unwrap().fee, dec!(0));
+    assert_eq!(engine.fees_collected(), dec!(0));
+}
+
+#[test]
+fn test_dispute_with_amount_lenient() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    // The amount on a dispute row is ignored by default
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_dispute(dispute_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0.0));
+    assert_eq!(account.held, dec!(100.0));
+}
+
+#[test]
+fn test_dispute_with_amount_strict() {
+    let config = EngineConfig {
+        strict_amounts: true,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    // Resolves and chargebacks are excluded since an amount makes them partial
+    let tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(tx), Err(RejectReason::UnexpectedAmount));
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100.0));
+    assert_eq!(account.held, dec!(0.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+}
+
+#[test]
+fn test_export_dirty_accounts() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let mut output = Vec::new();
+    engine.export_dirty_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n"
+    );
+
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 2,
+        tx_id: 2,
+        amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let mut output = Vec::new();
+    engine.export_dirty_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n2,50.0000,0.0000,50.0000,false\n"
+    );
+
+    // Nothing changed since the last export, and rejected transactions change nothing
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(500.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert!(engine.handle_withdrawal(withdrawal_tx).is_err());
+    let mut output = Vec::new();
+    engine.export_dirty_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n");
+
+    // References are exported as in `export_accounts`
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 4,
+        amount: Some(dec!(10.0)),
+        currency: None,
+        reference: Some("INV-4".to_string()),
+        seq: None,
+    };
+    engine.apply(deposit_tx).unwrap();
+    let mut output = Vec::new();
+    engine.export_dirty_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked,reference\n1,110.0000,0.0000,110.0000,false,INV-4\n"
+    );
+}
+
+#[test]
+fn test_reordered_columns() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/reordered_columns.csv");
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(File::open(path).unwrap()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-40.0));
+    assert_eq!(account.held, dec!(100.0));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(20.0));
+}
+
+#[test]
+fn test_comments_and_blank_lines() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/comments.csv");
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(File::open(path).unwrap()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100.0));
+    assert_eq!(account.held, dec!(25.0));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(6.0));
+    assert_eq!(engine.counts().malformed, 0);
+    assert_eq!(engine.counts().total(), 6);
+}
+
+#[test]
+fn test_missing_client_column() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,tx,amount\ndeposit,1,100.0\n";
+    let err = engine.process_transactions(input.as_bytes()).unwrap_err();
+    assert!(err.to_string().contains("`client`"));
+    assert!(matches!(err, AccountantError::MissingColumn("client")));
+    assert!(engine.accounts.is_empty());
+}
+
+#[test]
+fn test_process_transactions_strict() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n";
+    engine.process_transactions_strict(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.0));
+}
+
+#[test]
+fn test_process_transactions_strict_malformed_row() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 deposit,one,2,50.0\n\
+                 deposit,1,3,25.0\n";
+    let err = engine.process_transactions_strict(input.as_bytes()).unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(matches!(err.kind, ProcessErrorKind::InvalidField("client")));
+    assert_eq!(err.to_string(), "line 3: malformed row: invalid `client` field");
+
+    // Rows before the error remain applied, rows after it are not
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.0));
+
+    // A row that cannot be read as CSV reports the reader's error
+    let err = engine.process_transactions_strict("type,client,tx,amount\ndeposit,1,4,1.0,x\n".as_bytes()).unwrap_err();
+    assert_eq!(err.line, 2);
+    assert!(matches!(err.kind, ProcessErrorKind::Csv(_)));
+}
+
+#[test]
+fn test_process_transactions_strict_rejected_transaction() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 withdrawal,1,2,150.0\n";
+    let err = engine.process_transactions_strict(input.as_bytes()).unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(matches!(err.kind, ProcessErrorKind::Rejected(RejectReason::InsufficientFunds)));
+    assert_eq!(err.to_string(), "line 3: transaction rejected: insufficient available funds");
+}
+
+#[test]
+fn test_transaction_counts() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.apply(deposit_tx).unwrap();
+
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(150.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert!(engine.apply(withdrawal_tx).is_err());
+
+    let counts = engine.counts();
+    assert_eq!(counts.get(TransactionType::Deposit), TypeCounts { applied: 1, rejected: 0 });
+    assert_eq!(counts.get(TransactionType::Withdrawal), TypeCounts { applied: 0, rejected: 1 });
+    assert_eq!(counts.get(TransactionType::Dispute), TypeCounts::default());
+}
+
+#[test]
+fn test_duplicate_dispute_rejected() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 dispute,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_dispute(dispute_tx), Err(RejectReason::AlreadyDisputed));
+
+    // The second dispute moves no further funds
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0.0));
+    assert_eq!(account.held, dec!(100.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Disputed);
+}
+
+#[test]
+fn test_reversal() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 deposit,1,2,50.0\n\
+                 reversal,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(50.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Reversed);
+    assert_eq!(engine.counts().get(TransactionType::Reversal).applied, 1);
+
+    // A reversed deposit can be neither disputed nor reversed again
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_dispute(dispute_tx), Err(RejectReason::InvalidDisputeStatus));
+    let reversal_tx = InputTransaction {
+        transaction_type: TransactionType::Reversal,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_reversal(reversal_tx), Err(RejectReason::InvalidDisputeStatus));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(50.0));
+}
+
+#[test]
+fn test_reversal_of_withdrawal_rejected() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 withdrawal,1,2,30.0\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let reversal_tx = InputTransaction {
+        transaction_type: TransactionType::Reversal,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_reversal(reversal_tx), Err(RejectReason::NotADeposit));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(70.0));
+}
+
+#[test]
+fn test_reversal_of_spent_deposit() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 withdrawal,1,2,30.0\n\
+                 reversal,1,1,\n";
+
+    // Rejected by default
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(70.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+    assert_eq!(engine.counts().get(TransactionType::Reversal).rejected, 1);
+
+    // Overdraws the account when allowed
+    let config = EngineConfig {
+        reversal_policy: ReversalPolicy::AllowNegative,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-30.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Reversed);
+}
+
+#[test]
+fn test_reversal_type_round_trip() {
+    let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    wtr.serialize(TransactionType::Reversal).unwrap();
+    let written = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+    assert_eq!(written, "reversal\n");
+
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).from_reader(written.as_bytes());
+    let read: TransactionType = rdr.deserialize().next().unwrap().unwrap();
+    assert_eq!(read, TransactionType::Reversal);
+}
+
+/// A writer whose reader has gone away, like stdout piped into a closed process.
+struct BrokenPipe;
+
+impl std::io::Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn test_export_to_broken_pipe() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let err = engine.export_accounts(BrokenPipe).unwrap_err();
+    assert!(matches!(&err, ExportError::Flush(e) if e.kind() == std::io::ErrorKind::BrokenPipe));
+
+    let err = engine.print_report(BrokenPipe).unwrap_err();
+    assert!(matches!(err, ExportError::Io { client_id: None, .. }));
+}
+
+#[test]
+fn test_process_files_in_order() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let paths = [fixtures.join("multi_file_1.csv"), fixtures.join("multi_file_2.csv")];
+    let engine = process_files(&paths).unwrap();
+
+    // The dispute in the second file references the deposit from the first
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-25.0));
+    assert_eq!(account.held, dec!(100.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Disputed);
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(40.0));
+}
+
+#[test]
+fn test_process_files_missing_file() {
+    let paths = [std::path::PathBuf::from("does_not_exist.csv")];
+    let err = process_files(&paths).err().unwrap();
+    assert!(err.to_string().starts_with("does_not_exist.csv:"));
+    match err {
+        AccountantError::File { path, source } => {
+            assert_eq!(path, paths[0]);
+            assert!(matches!(*source, AccountantError::Io(_)));
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn test_process_sharded_dir() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/shards");
+    let engine = process_sharded_dir(&dir).unwrap();
+
+    assert_eq!(engine.accounts.len(), 4);
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0.0));
+    assert_eq!(account.held, dec!(100.0));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(40.0));
+    assert_eq!(engine.accounts.get(&3).unwrap().available, dec!(20.0));
+    assert_eq!(engine.accounts.get(&4).unwrap().available, dec!(5.0));
+    assert_eq!(engine.counts().total(), 6);
+
+    // Same result as processing the shards one after another
+    let paths = [dir.join("client_0.csv"), dir.join("client_1.csv")];
+    assert!(engine.diff(&process_files(&paths).unwrap()).is_empty());
+}
+
+#[test]
+fn test_process_sharded_dir_worker_panic() {
+    let dir = std::env::temp_dir().join(format!("rs-accountant-panicking-shard-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("client_0.csv"), "type,client,tx,amount\ndeposit,1,1,100\n").unwrap();
+    // Overdrawing with funds held fails the held funds check, which panics
+    std::fs::write(
+        dir.join("client_1.csv"),
+        "type,client,tx,amount\ndeposit,2,2,100\ndispute,2,2,\nwithdrawal,2,3,50\n",
+    )
+    .unwrap();
+
+    let config = EngineConfig {
+        held_check: Some(HeldCheck::Panic),
+        overdraft_limit: Some(dec!(50)),
+        ..Default::default()
+    };
+    let result = process_sharded_dir_with_config(&dir, config);
+    std::fs::remove_dir_all(&dir).unwrap();
+    match result {
+        Err(AccountantError::File { path, source }) => {
+            assert_eq!(path, dir.join("client_1.csv"));
+            assert!(matches!(*source, AccountantError::WorkerPanicked));
+        }
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("expected an error"),
+    }
+}
+
+#[test]
+fn test_unlock_account() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 deposit,1,2,20.0\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert!(engine.accounts.get(&1).unwrap().locked);
+
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(50.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_deposit(deposit_tx.clone()), Err(RejectReason::AccountLocked));
+
+    engine.unlock_account(1).unwrap();
+    assert!(!engine.accounts.get(&1).unwrap().locked);
+    assert_eq!(engine.admin_actions(), &[AdminAction::Unlock(1)]);
+
+    engine.handle_deposit(deposit_tx).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(70.0));
+
+    // Unlocking an unlocked account is an error
+    assert_eq!(engine.unlock_account(1), Err(UnlockError::NotLocked(1)));
+}
+
+#[test]
+fn test_unlock_unknown_account() {
+    let mut engine = PaymentEngine::new();
+    assert_eq!(engine.unlock_account(1), Err(UnlockError::UnknownClient(1)));
+    assert!(engine.admin_actions().is_empty());
+}
+
+#[test]
+fn test_malformed_rows_counted() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 deposit,one,2,50.0\n\
+                 deposit,1,3,abc\n\
+                 withdrawal,1,4,25.0\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.counts().malformed, 2);
+    assert_eq!(engine.counts().deposit.applied, 1);
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(75.0));
+}
+
+#[test]
+fn test_positive_adjustment() {
+    let mut engine = PaymentEngine::new();
+    let adjustment_tx = InputTransaction {
+        transaction_type: TransactionType::Adjustment,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(25.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_adjustment(adjustment_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(25.0));
+    assert_eq!(engine.transactions.get(&1).unwrap().amount, dec!(25.0));
+
+    // Adjustments can be disputed like any other transaction
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_dispute(dispute_tx).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0.0));
+    assert_eq!(account.held, dec!(25.0));
+}
+
+#[test]
+fn test_negative_adjustment() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 adjustment,1,2,-40.0\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(60.0));
+    assert_eq!(engine.transactions.get(&2).unwrap().amount, dec!(-40.0));
+    assert_eq!(engine.counts().get(TransactionType::Adjustment).applied, 1);
+}
+
+#[test]
+fn test_negative_adjustment_insufficient_funds() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let adjustment_tx = InputTransaction {
+        transaction_type: TransactionType::Adjustment,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(-100.01)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_adjustment(adjustment_tx), Err(RejectReason::InsufficientFunds));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.0));
+    assert!(!engine.transactions.contains_key(&2));
+
+    let adjustment_tx = InputTransaction {
+        transaction_type: TransactionType::Adjustment,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_adjustment(adjustment_tx), Err(RejectReason::ZeroAmount));
+}
+
+#[test]
+fn test_rounding_modes() {
+    assert_eq!(RoundingMode::default(), RoundingMode::MidpointNearestEven);
+    assert_eq!(RoundingMode::MidpointNearestEven.round(dec!(100.005), 2), dec!(100.00));
+    assert_eq!(RoundingMode::MidpointAwayFromZero.round(dec!(100.005), 2), dec!(100.01));
+    assert_eq!(RoundingMode::MidpointNearestEven.round(dec!(100.015), 2), dec!(100.02));
+    assert_eq!(RoundingMode::ToZero.round(dec!(100.019), 2), dec!(100.01));
+}
+
+#[test]
+fn test_fee_rounding() {
+    let fee_schedule = Some(FeeSchedule { flat: dec!(0), rate: dec!(0.01) });
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(1000.50)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+
+    // The raw fee of 10.005 is rounded half to even by default
+    let config = EngineConfig { fee_schedule, scale: Some(2), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.handle_deposit(deposit_tx.clone()).unwrap();
+    assert_eq!(engine.fees_collected(), dec!(10.00));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(990.50));
+
+    let config = EngineConfig {
+        fee_schedule,
+        scale: Some(2),
+        rounding: RoundingMode::MidpointAwayFromZero,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.handle_deposit(deposit_tx.clone()).unwrap();
+    assert_eq!(engine.fees_collected(), dec!(10.01));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(990.49));
+
+    // Without a scale the fee keeps full precision
+    let config = EngineConfig { fee_schedule, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.handle_deposit(deposit_tx).unwrap();
+    assert_eq!(engine.fees_collected(), dec!(10.005));
+}
+
+#[test]
+fn test_export_rounded_to_scale() {
+    let config = EngineConfig { scale: Some(2), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\ndeposit,1,1,100.125\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Balances keep full precision, the output is rounded
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.125));
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,100.1200,0.0000,100.1200,false\n"
+    );
+}
+
+#[test]
+fn test_export_preserves_sums() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.004\n\
+                 deposit,2,2,1.004\n\
+                 deposit,3,3,1.004\n\
+                 deposit,3,4,0.5\n\
+                 dispute,3,4,\n";
+    let config = EngineConfig { scale: Some(2), checksum_row: true, ..Default::default() };
+
+    // Rounding each account independently loses a cent of the 3.512 grand total
+    let mut engine = PaymentEngine::with_config(config.clone());
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,1.0000,0.0000,1.0000,false\n\
+         2,1.0000,0.0000,1.0000,false\n\
+         3,1.0000,0.5000,1.5000,false\n\
+         checksum,,,3.5000,\n"
+    );
+
+    // With sum preservation the lost cent goes to the first of the equal remainders
+    let mut engine = PaymentEngine::with_config(EngineConfig { preserve_sums: true, ..config });
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,1.0100,0.0000,1.0100,false\n\
+         2,1.0000,0.0000,1.0000,false\n\
+         3,1.0000,0.5000,1.5000,false\n\
+         checksum,,,3.5100,\n"
+    );
+}
+
+#[test]
+fn test_out_of_range_client_id_skipped() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 deposit,99999,2,50.0\n\
+                 deposit,2,3,20.0\n\
+                 withdrawal,1,4,30.0\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.counts().malformed, 1);
+    assert_eq!(engine.accounts.len(), 2);
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(70.0));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(20.0));
+}
+
+#[test]
+fn test_partial_resolve() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,30\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(30));
+    assert_eq!(account.held, dec!(70));
+    let stored_tx = engine.transactions.get(&1).unwrap();
+    assert_eq!(stored_tx.dispute_status, DisputeStatus::Disputed);
+    assert_eq!(stored_tx.held, dec!(70));
+    assert_eq!(engine.disputed_exposure(1), dec!(70));
+
+    // Releasing more than is still held is rejected
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(70.01)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_resolve(resolve_tx), Err(RejectReason::ExceedsHeldAmount));
+
+    // A resolve without an amount releases the remainder
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_resolve(resolve_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Resolved);
+}
+
+#[test]
+fn test_partial_resolves_to_completion() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,60\n\
+                 resolve,1,1,40\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Resolved);
+}
+
+#[test]
+fn test_chargeback_after_partial_resolve() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,30\n\
+                 chargeback,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Only the funds still held are charged back
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(30));
+    assert_eq!(account.held, dec!(0));
+    assert!(account.locked);
+}
+
+#[test]
+fn test_client_ids_sorted() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,7,1,10\n\
+                 deposit,2,2,10\n\
+                 deposit,5,3,10\n\
+                 deposit,2,4,10\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.num_clients(), 3);
+    assert_eq!(engine.client_ids().collect::<Vec<_>>(), vec![2, 5, 7]);
+}
+
+#[test]
+fn test_dispute_on_locked_account_rejected_by_default() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_dispute(dispute_tx), Err(RejectReason::AccountLocked));
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(50));
+    assert_eq!(account.held, dec!(0));
+}
+
+#[test]
+fn test_dispute_on_locked_account_allowed() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        allow_dispute_on_locked: true,
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 deposit,1,3,25\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 dispute,1,2,\n\
+                 dispute,1,3,\n\
+                 resolve,1,3,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(25));
+    assert_eq!(account.held, dec!(50));
+    assert!(account.locked);
+
+    let chargeback_tx = InputTransaction {
+        transaction_type: TransactionType::Chargeback,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_chargeback(chargeback_tx).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(25));
+    assert_eq!(account.held, dec!(0));
+    assert!(account.locked);
+
+    // Other transaction types stay frozen
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 4,
+        amount: Some(dec!(10)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_deposit(deposit_tx), Err(RejectReason::AccountLocked));
+}
+
+#[test]
+fn test_accrue_interest() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        scale: Some(2),
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,200.00\n\
+                 deposit,2,2,300.00\n\
+                 dispute,2,2,\n\
+                 chargeback,2,2,\n\
+                 deposit,3,3,50.00\n\
+                 deposit,3,4,10.00\n\
+                 dispute,3,4,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    engine.accrue_interest(dec!(0.01));
+
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(202.00));
+    // Locked accounts are untouched
+    let locked = engine.accounts.get(&2).unwrap();
+    assert_eq!(locked.available, dec!(0));
+    assert_eq!(locked.total(), dec!(0));
+    // Held funds earn no interest
+    let account = engine.accounts.get(&3).unwrap();
+    assert_eq!(account.available, dec!(50.50));
+    assert_eq!(account.held, dec!(10.00));
+
+    assert_eq!(
+        engine.interest_credits(),
+        &[
+            InterestCredit { client_id: 1, amount: dec!(2.00) },
+            InterestCredit { client_id: 3, amount: dec!(0.50) },
+        ]
+    );
+    assert_eq!(engine.interest_paid(), dec!(2.50));
+}
+
+#[test]
+fn test_accrue_interest_rounding() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        scale: Some(2),
+        rounding: RoundingMode::ToZero,
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,123.45\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    engine.accrue_interest(dec!(0.01));
+
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(124.68));
+}
+
+#[test]
+fn test_export_checksum_row() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.25\n\
+                 deposit,2,2,50.5\n\
+                 dispute,2,2,\n\
+                 deposit,3,3,0.0001\n";
+    let mut plain = PaymentEngine::new();
+    plain.process_transactions(input.as_bytes()).unwrap();
+    let mut checked = PaymentEngine::with_config(EngineConfig {
+        checksum_row: true,
+        ..Default::default()
+    });
+    checked.process_transactions(input.as_bytes()).unwrap();
+
+    let mut plain_output = Vec::new();
+    plain.export_accounts(&mut plain_output).unwrap();
+    let mut checked_output = Vec::new();
+    checked.export_accounts(&mut checked_output).unwrap();
+
+    // The checksum row is appended after the unchanged account rows
+    let plain_output = String::from_utf8(plain_output).unwrap();
+    let checked_output = String::from_utf8(checked_output).unwrap();
+    let checksum_line = checked_output.strip_prefix(plain_output.as_str()).unwrap();
+    assert_eq!(checksum_line, "checksum,,,150.7501,\n");
+
+    let sum: Decimal = plain.accounts.values().map(|a| a.total()).sum();
+    assert_eq!(sum, dec!(150.7501));
+
+    // An empty engine still gets the header above its checksum row
+    let empty = PaymentEngine::with_config(EngineConfig { checksum_row: true, ..Default::default() });
+    let mut output = Vec::new();
+    empty.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         checksum,,,0.0000,\n"
+    );
+}
+
+#[test]
+fn test_merge_combines_overlapping_clients() {
+    let mut first = PaymentEngine::new();
+    first
+        .process_transactions(
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             deposit,2,2,20\n\
+             dispute,2,2,\n\
+             withdrawal,1,3,500\n"
+                .as_bytes(),
+        )
+        .unwrap();
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions(
+            "type,client,tx,amount\n\
+             deposit,1,4,50\n\
+             dispute,1,4,\n\
+             deposit,2,5,30\n\
+             dispute,2,5,\n\
+             chargeback,2,5,\n\
+             deposit,3,6,5\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+    first.merge(second).unwrap();
+
+    let account = first.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.held, dec!(50));
+    assert!(!account.locked);
+    let account = first.accounts.get(&2).unwrap();
+    assert_eq!(account.available, dec!(0));
+    assert_eq!(account.held, dec!(20));
+    assert!(account.locked);
+    assert_eq!(first.accounts.get(&3).unwrap().available, dec!(5));
+
+    assert_eq!(first.transactions.len(), 5);
+    assert_eq!(first.disputed_exposure(1), dec!(50));
+    assert_eq!(first.disputed_exposure(2), dec!(20));
+    assert_eq!(first.counts().deposit.applied, 5);
+    assert_eq!(first.counts().withdrawal.rejected, 1);
+
+    // A dispute can still be resolved after the merge
+    first
+        .process_transactions("type,client,tx,amount\nresolve,1,4,\n".as_bytes())
+        .unwrap();
+    assert_eq!(first.accounts.get(&1).unwrap().available, dec!(150));
+}
+
+#[test]
+fn test_merge_rejects_duplicate_transactions() {
+    let mut first = PaymentEngine::new();
+    first
+        .process_transactions("type,client,tx,amount\ndeposit,1,1,100\n".as_bytes())
+        .unwrap();
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions(
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             dispute,1,1,\n\
+             deposit,2,2,10\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+    assert_eq!(first.merge(second), Err(MergeError::DuplicateTransaction(1)));
+
+    // Nothing was merged
+    assert_eq!(first.num_clients(), 1);
+    let account = first.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.held, dec!(0));
+}
+
+#[test]
+fn test_merge_rejects_currency_mismatch() {
+    let mut first = PaymentEngine::new();
+    first
+        .process_transactions("type,client,tx,amount,currency\ndeposit,1,1,100,USD\n".as_bytes())
+        .unwrap();
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions("type,client,tx,amount,currency\ndeposit,1,2,50,EUR\n".as_bytes())
+        .unwrap();
+
+    assert_eq!(first.merge(second), Err(MergeError::CurrencyMismatch(1)));
+    let account = first.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.currency.as_deref(), Some("USD"));
+
+    // An account without an established currency takes the other's
+    let mut first = PaymentEngine::new();
+    first.process_transactions("type,client,tx,amount\ndeposit,1,1,100\n".as_bytes()).unwrap();
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions("type,client,tx,amount,currency\ndeposit,1,2,50,EUR\n".as_bytes())
+        .unwrap();
+    first.merge(second).unwrap();
+    assert_eq!(first.accounts.get(&1).unwrap().currency.as_deref(), Some("EUR"));
+}
+
+#[test]
+fn test_net_deposits_overflow() {
+    let config = EngineConfig { held_check: Some(HeldCheck::Error), ..Default::default() };
+    let deposit = |client_id, tx_id, amount| InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id,
+        tx_id,
+        amount: Some(amount),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+
+    // Engine-wide totals that would overflow fail the merge before anything changes
+    let mut first = PaymentEngine::with_config(config.clone());
+    first.apply(deposit(1, 1, Decimal::MAX - dec!(1))).unwrap();
+    let mut second = PaymentEngine::with_config(config.clone());
+    second.apply(deposit(2, 2, Decimal::MAX - dec!(1))).unwrap();
+    assert_eq!(first.merge(second), Err(MergeError::Overflow(None)));
+    assert!(!first.accounts.contains_key(&2));
+
+    // So do opening balances, and interest is skipped
+    let mut engine = PaymentEngine::with_config(config);
+    engine.apply(deposit(1, 1, Decimal::MAX - dec!(100))).unwrap();
+    engine.apply(deposit(2, 2, dec!(90))).unwrap();
+    assert_eq!(
+        engine.set_opening_balance(3, dec!(20), false),
+        Err(OpeningBalanceError::Overflow(3))
+    );
+    assert!(!engine.accounts.contains_key(&3));
+    engine.accrue_interest(dec!(0.5));
+    assert!(engine.interest_credits().is_empty());
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(90));
+}
+
+#[test]
+fn test_merge_carries_references() {
+    let mut first = PaymentEngine::new();
+    first
+        .process_transactions("type,client,tx,amount,reference\ndeposit,1,1,10,a\ndeposit,2,2,10,b\n".as_bytes())
+        .unwrap();
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions("type,client,tx,amount,reference\ndeposit,1,3,10,c\ndeposit,2,4,10,\n".as_bytes())
+        .unwrap();
+
+    first.merge(second).unwrap();
+    assert_eq!(first.accounts.get(&1).unwrap().last_reference.as_deref(), Some("c"));
+    assert_eq!(first.accounts.get(&2).unwrap().last_reference.as_deref(), Some("b"));
+}
+
+#[test]
+fn test_merge_rejects_overflow() {
+    let mut first = PaymentEngine::new();
+    first.process_transactions("type,client,tx,amount\ndeposit,1,1,10\n".as_bytes()).unwrap();
+    first.accounts.get_mut(&1).unwrap().available = Decimal::MAX;
+    let mut second = PaymentEngine::new();
+    second
+        .process_transactions("type,client,tx,amount\ndeposit,1,2,10\ndeposit,2,3,5\n".as_bytes())
+        .unwrap();
+
+    assert_eq!(first.merge(second), Err(MergeError::Overflow(Some(1))));
+    assert_eq!(first.accounts.get(&1).unwrap().available, Decimal::MAX);
+    assert!(!first.accounts.contains_key(&2));
+}
+
+#[test]
+fn test_unknown_type_counted_separately() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 depsoit,1,2,50.0\n\
+                 deposit,one,3,50.0\n\
+                 withdrawal,1,4,25.0\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.counts().unknown_type, 1);
+    assert_eq!(engine.counts().malformed, 1);
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(75.0));
+}
+
+#[test]
+fn test_process_transactions_strict_unknown_type() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 depsoit,1,2,50.0\n";
+    let err = engine.process_transactions_strict(input.as_bytes()).unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(matches!(&err.kind, ProcessErrorKind::UnknownType(value) if value == "depsoit"));
+    assert_eq!(err.to_string(), "line 3: unknown transaction type `depsoit`");
+}
+
+#[test]
+fn test_transaction_type_from_str() {
+    assert_eq!("chargeback".parse(), Ok(TransactionType::Chargeback));
+    assert_eq!(
+        "depsoit".parse::<TransactionType>(),
+        Err(UnknownTransactionType("depsoit".to_string()))
+    );
+}
+
+#[test]
+fn test_export_accounts_filtered() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,3,1,30\n\
+                 deposit,1,2,10\n\
+                 deposit,2,3,20\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let mut output = Vec::new();
+    engine.export_accounts_filtered(&mut output, &HashSet::from([3, 1])).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,10.0000,0.0000,10.0000,false\n\
+         3,30.0000,0.0000,30.0000,false\n"
+    );
+
+    let mut output = Vec::new();
+    engine.export_accounts_filtered(&mut output, &HashSet::from([42])).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n");
+}
+
+#[test]
+fn test_dispute_held_ceiling() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        max_held_per_client: Some(dec!(100)),
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,40\n\
+                 deposit,1,2,60\n\
+                 deposit,1,3,0.01\n\
+                 dispute,1,1,\n\
+                 dispute,1,2,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(100));
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 3,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_dispute(dispute_tx), Err(RejectReason::HeldCeilingExceeded));
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0.01));
+    assert_eq!(account.held, dec!(100));
+    assert_eq!(engine.transactions.get(&3).unwrap().dispute_status, DisputeStatus::NotDisputed);
+}
+
+#[test]
+fn test_apply_one_returns_snapshot() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(dec!(100.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let (result, snapshot) = engine.apply_one(deposit_tx);
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        snapshot,
+        Some(AccountSnapshot {
+            client_id: 1,
+            available: dec!(100.0),
+            held: dec!(0),
+            total: dec!(100.0),
+            locked: false,
+        })
+    );
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let (result, snapshot) = engine.apply_one(dispute_tx);
+    assert_eq!(result, Ok(()));
+    let snapshot = snapshot.unwrap();
+    assert_eq!(snapshot.available, dec!(0));
+    assert_eq!(snapshot.held, dec!(100.0));
+    assert_eq!(snapshot.total, dec!(100.0));
+
+    // A rejected transaction still reports the unchanged account
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(10.0)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let (result, after) = engine.apply_one(withdrawal_tx);
+    assert_eq!(result, Err(RejectReason::InsufficientFunds));
+    assert_eq!(after, Some(snapshot));
+
+    // Clients without an account have no snapshot
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 2,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply_one(dispute_tx), (Err(RejectReason::ClientMismatch), None));
+}
+
+#[test]
+fn test_export_accounts_with_format() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,5.5\n\
+                 deposit,1,2,100\n\
+                 dispute,1,2,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let format = CsvFormat {
+        delimiter: b';',
+        quote_style: csv::QuoteStyle::Always,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    engine.export_accounts_with(&mut output, &format).unwrap();
+    assert_eq!(
+        String::from_utf8(output.clone()).unwrap(),
+        "\"client\";\"available\";\"held\";\"total\";\"locked\"\n\
+         \"1\";\"0.0000\";\"100.0000\";\"100.0000\";\"false\"\n\
+         \"2\";\"5.5000\";\"0.0000\";\"5.5000\";\"false\"\n"
+    );
+
+    // Reading it back with the same dialect gives the default output's records
+    let mut rdr = csv::ReaderBuilder::new().delimiter(b';').from_reader(output.as_slice());
+    let reread: Vec<csv::StringRecord> = rdr.records().collect::<Result<_, _>>().unwrap();
+    let mut default_output = Vec::new();
+    engine.export_accounts(&mut default_output).unwrap();
+    let mut rdr = csv::Reader::from_reader(default_output.as_slice());
+    let expected: Vec<csv::StringRecord> = rdr.records().collect::<Result<_, _>>().unwrap();
+    assert_eq!(reread, expected);
+}
+
+#[test]
+fn test_deposit_overflow_rejected() {
+    let mut engine = PaymentEngine::new();
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(Decimal::MAX - dec!(1)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_deposit(deposit_tx).unwrap();
+
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(dec!(2)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_deposit(deposit_tx), Err(RejectReason::Overflow));
+    assert!(!engine.transactions.contains_key(&2));
+
+    let adjustment_tx = InputTransaction {
+        transaction_type: TransactionType::Adjustment,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(2)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_adjustment(adjustment_tx), Err(RejectReason::Overflow));
+
+    // Interest that would overflow is skipped rather than credited
+    engine.accrue_interest(dec!(0.5));
+    assert!(engine.interest_credits().is_empty());
+
+    assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::MAX - dec!(1));
+}
+
+#[test]
+fn test_resolve_and_reversal_overflow_rejected() {
+    let config = EngineConfig { reversal_policy: ReversalPolicy::AllowNegative, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 dispute,1,1,\n\
+                 deposit,2,2,10\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    engine.accounts.get_mut(&1).unwrap().available = Decimal::MAX;
+    engine.accounts.get_mut(&2).unwrap().available = Decimal::MIN;
+
+    let err = engine.apply(InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    });
+    assert_eq!(err, Err(RejectReason::Overflow));
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(10));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Disputed);
+
+    let err = engine.apply(InputTransaction {
+        transaction_type: TransactionType::Reversal,
+        client_id: 2,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    });
+    assert_eq!(err, Err(RejectReason::Overflow));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, Decimal::MIN);
+    assert_eq!(engine.transactions.get(&2).unwrap().dispute_status, DisputeStatus::NotDisputed);
+}
+
+#[test]
+fn test_lock_hook_fires_per_chargeback() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        allow_dispute_on_locked: true,
+        ..Default::default()
+    });
+    let locked = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&locked);
+    engine.on_lock(move |client_id, account| {
+        assert!(account.locked);
+        seen.lock().unwrap().push((client_id, account.total()));
+    });
+
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 deposit,2,3,30\n\
+                 dispute,1,1,\n\
+                 dispute,2,3,\n\
+                 resolve,2,3,\n\
+                 chargeback,1,1,\n\
+                 chargeback,2,3,\n\
+                 dispute,1,2,\n\
+                 chargeback,1,2,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(*locked.lock().unwrap(), vec![(1, dec!(50)), (1, dec!(0))]);
+}
+
+#[test]
+fn test_retention_window_evicts_oldest() {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        retention: Some(2),
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,1,2,20\n\
+                 dispute,1,2,\n\
+                 deposit,1,3,30\n\
+                 deposit,1,4,40\n\
+                 withdrawal,1,5,5\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Transaction 2 was under dispute when it left the window, so it is kept and requeued
+    let mut stored: Vec<_> = engine.transactions.keys().copied().collect();
+    stored.sort_unstable();
+    assert_eq!(stored, vec![2, 5]);
+    assert_eq!(engine.evictions(), 3);
+
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_dispute(dispute_tx), Err(RejectReason::UnknownTransaction));
+
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.handle_resolve(resolve_tx).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(95));
+
+    // Once its dispute has ended, it is evicted as the oldest again
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,6,1\n".as_bytes()).unwrap();
+    let mut stored: Vec<_> = engine.transactions.keys().copied().collect();
+    stored.sort_unstable();
+    assert_eq!(stored, vec![5, 6]);
+    assert_eq!(engine.evictions(), 4);
+}
+
+#[test]
+fn test_diff_reports_differing_clients() {
+    let mut left = PaymentEngine::new();
+    left.process_transactions(
+        "type,client,tx,amount\n\
+         deposit,1,1,100\n\
+         deposit,2,2,50\n\
+         dispute,2,2,\n\
+         deposit,3,3,10\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut right = PaymentEngine::new();
+    right
+        .process_transactions(
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             deposit,2,2,50\n\
+             deposit,4,4,5\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+    assert!(left.diff(&left).is_empty());
+
+    let diffs = right.diff(&left);
+    assert_eq!(diffs.iter().map(|d| d.client_id).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+    let client_2 = &diffs[0];
+    assert_eq!(client_2.left.unwrap().held, dec!(0));
+    assert_eq!(client_2.right.unwrap().held, dec!(50));
+    assert_eq!(
+        client_2.dispute_statuses,
+        vec![(2, Some(DisputeStatus::NotDisputed), Some(DisputeStatus::Disputed))]
+    );
+
+    let client_3 = &diffs[1];
+    assert_eq!(client_3.left, None);
+    assert_eq!(client_3.right.unwrap().available, dec!(10));
+    assert_eq!(client_3.dispute_statuses, vec![(3, None, Some(DisputeStatus::NotDisputed))]);
+
+    let client_4 = &diffs[2];
+    assert_eq!(client_4.right, None);
+}
+
+#[test]
+fn test_held_funds_never_negative() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 dispute,1,1,\n\
+                 dispute,1,2,\n\
+                 chargeback,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(50));
+
+    // A second chargeback of the same transaction is rejected
+    let chargeback_tx = InputTransaction {
+        transaction_type: TransactionType::Chargeback,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(chargeback_tx), Err(RejectReason::AlreadyChargedBack));
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(50));
+
+    // Held funds released behind the engine's back cannot be released again
+    let account = engine.accounts.get_mut(&1).unwrap();
+    account.held = dec!(20);
+    account.locked = false;
+    let chargeback_tx = InputTransaction {
+        transaction_type: TransactionType::Chargeback,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(chargeback_tx), Err(RejectReason::InsufficientHeldFunds));
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(resolve_tx), Err(RejectReason::InsufficientHeldFunds));
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(20));
+    assert_eq!(engine.counts().chargeback.rejected, 2);
+}
+
+#[test]
+fn test_from_transactions() {
+    let tx = |transaction_type, tx_id, amount| InputTransaction {
+        transaction_type,
+        client_id: 1,
+        tx_id,
+        amount,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let engine = PaymentEngine::from_transactions(vec![
+        tx(TransactionType::Deposit, 1, Some(dec!(100))),
+        tx(TransactionType::Withdrawal, 2, Some(dec!(30))),
+        tx(TransactionType::Withdrawal, 3, Some(dec!(500))),
+        tx(TransactionType::Dispute, 1, None),
+    ]);
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-30));
+    assert_eq!(account.held, dec!(100));
+    assert_eq!(engine.counts().withdrawal.applied, 1);
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+}
+
+#[test]
+fn test_withdrawal_blocked_while_dispute_leaves_available_negative() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,80\n\
+                 dispute,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-80));
+
+    // Without an overdraft limit nothing can be withdrawn from negative available funds
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(0.0001)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal_tx), Err(RejectReason::InsufficientFunds));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-80));
+}
+
+#[test]
+fn test_withdrawal_dispute_credits_client() {
+    // Disputing a withdrawal used to move its amount out of available funds like a deposit:
+    // the dispute below left available at -20 with 60 held, and the chargeback then took the
+    // 60 away, charging the client twice for one withdrawal
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,60\n\
+                 dispute,1,2,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(40));
+    assert_eq!(account.held, dec!(60));
+
+    // The held funds are the client's only if the chargeback goes through
+    let withdrawal = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 3,
+        amount: Some(dec!(41)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal), Err(RejectReason::InsufficientFunds));
+
+    let mut charged_back = PaymentEngine::new();
+    charged_back.process_transactions(input.as_bytes()).unwrap();
+    charged_back.process_transactions("type,client,tx,amount\nchargeback,1,2,\n".as_bytes()).unwrap();
+    let account = charged_back.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(100));
+    assert_eq!(account.held, dec!(0));
+    assert!(account.locked);
+
+    // Resolving upholds the withdrawal, so nothing is credited
+    engine.process_transactions("type,client,tx,amount\nresolve,1,2,\n".as_bytes()).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(40));
+    assert_eq!(account.held, dec!(0));
+}
+
+#[test]
+fn test_overdraft_limit() {
+    let config = EngineConfig { overdraft_limit: Some(dec!(50)), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,130\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-30));
+
+    let withdrawal = |tx_id, amount| InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id,
+        amount: Some(amount),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal(3, dec!(20.0001))), Err(RejectReason::InsufficientFunds));
+    engine.apply(withdrawal(4, dec!(20))).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-50));
+}
+
+#[test]
+fn test_overdraft_limit_counts_disputed_funds() {
+    let config = EngineConfig { overdraft_limit: Some(dec!(50)), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,80\n\
+                 dispute,1,1,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // A dispute may push available funds past the limit; withdrawals are then blocked
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-80));
+    assert_eq!(account.held, dec!(100));
+    let withdrawal = |tx_id, amount| InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id,
+        amount: Some(amount),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal(3, dec!(1))), Err(RejectReason::InsufficientFunds));
+
+    // Once resolved, the overdraft is available again, down to -50
+    let resolve_tx = InputTransaction {
+        transaction_type: TransactionType::Resolve,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    engine.apply(resolve_tx).unwrap();
+    engine.apply(withdrawal(4, dec!(70))).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-50));
+
+    // A smaller dispute leaves available funds within the limit, so the rest can be withdrawn
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        overdraft_limit: Some(dec!(50)),
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,10\n\
+                 withdrawal,1,3,80\n\
+                 dispute,1,2,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20));
+    engine.apply(withdrawal(4, dec!(70))).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-50));
+    assert_eq!(account.held, dec!(10));
+}
+
+#[test]
+fn test_dispute_of_deposit_rejected_by_lock() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 deposit,1,2,50\n";
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 2,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+
+    // Without auditing the rejected deposit is not stored
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert!(!engine.transactions.contains_key(&2));
+    assert_eq!(engine.apply(dispute_tx.clone()), Err(RejectReason::UnknownTransaction));
+
+    let config = EngineConfig { audit: true, allow_dispute_on_locked: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.transactions.get(&2).unwrap().dispute_status, DisputeStatus::Rejected);
+    assert_eq!(engine.apply(dispute_tx), Err(RejectReason::RejectedDeposit));
+
+    // Balances are unaffected and the deposit still counts as rejected
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.counts().deposit.rejected, 1);
+    assert_eq!(engine.audit_entries().len(), 3);
+}
+
+#[test]
+fn test_counts_total() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,500\n\
+                 deposit,1,x,1\n\
+                 refund,1,3,1\n\
+                 dispute,1,1,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.counts().total(), 5);
+}
+
+#[test]
+fn test_reject_unknown_clients() {
+    let input = "type,client,tx,amount\n\
+                 withdrawal,7,1,10\n\
+                 dispute,8,1,\n";
+
+    // By default the rejected withdrawal leaves an empty account behind
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert!(engine.accounts.contains_key(&7));
+
+    let config = EngineConfig { reject_unknown_clients: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert!(engine.accounts.is_empty());
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+    assert_eq!(engine.counts().dispute.rejected, 1);
+
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n");
+
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 7,
+        tx_id: 2,
+        amount: Some(dec!(10)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal_tx), Err(RejectReason::UnknownClient));
+}
+
+#[test]
+fn test_accounts_to_json_value() {
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,5.5\n\
+                 deposit,1,2,10\n\
+                 dispute,1,2,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let value = engine.accounts_to_json_value().unwrap();
+    assert_eq!(
+        value,
+        serde_json::json!([
+            {"client": 1, "available": "0.0000", "held": "10.0000", "total": "10.0000", "locked": false},
+            {"client": 2, "available": "5.5000", "held": "0.0000", "total": "5.5000", "locked": false},
+        ])
+    );
+}
+
+#[test]
+fn test_export_accounts_json() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=300u32 {
+        input += &format!("deposit,{},{},{}.{}\n", tx % 50, tx, tx, tx % 7);
+    }
+    input += "dispute,3,3,\nwithdrawal,4,301,1.5\n";
+
+    for preserve_sums in [false, true] {
+        let config = EngineConfig { scale: Some(2), preserve_sums, ..Default::default() };
+        let mut engine = PaymentEngine::with_config(config);
+        engine.process_transactions(input.as_bytes()).unwrap();
+
+        let mut output = Vec::new();
+        engine.export_accounts_json(&mut output).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(parsed, engine.accounts_to_json_value().unwrap());
+
+        let clients: Vec<_> = parsed.as_array().unwrap().iter().map(|account| account["client"].as_u64().unwrap()).collect();
+        assert_eq!(clients, (0..50).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn test_currency_mismatch() {
+    let input = "type,client,tx,amount,currency\n\
+                 deposit,1,1,100,USD\n\
+                 withdrawal,1,2,10,EUR\n\
+                 deposit,1,3,5,EUR\n\
+                 withdrawal,1,4,10,USD\n\
+                 withdrawal,1,5,10,\n\
+                 dispute,1,1,,EUR\n\
+                 deposit,2,6,50,\n\
+                 deposit,2,7,50,EUR\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.currency.as_deref(), Some("USD"));
+    assert_eq!(account.available, dec!(80));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+    assert_eq!(engine.counts().deposit.rejected, 1);
+    assert_eq!(engine.counts().dispute.rejected, 1);
+
+    // The first deposit naming a currency establishes it
+    let account = engine.accounts.get(&2).unwrap();
+    assert_eq!(account.currency.as_deref(), Some("EUR"));
+    assert_eq!(account.available, dec!(100));
+
+    let withdrawal_tx = InputTransaction {
+        transaction_type: TransactionType::Withdrawal,
+        client_id: 1,
+        tx_id: 8,
+        amount: Some(dec!(1)),
+        currency: Some("GBP".to_string()),
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(withdrawal_tx), Err(RejectReason::CurrencyMismatch));
+}
+
+#[test]
+fn test_export_reports_total_overflow() {
+    let mut engine = PaymentEngine::new();
+    let mut account = Account::new(3);
+    account.available = Decimal::MAX;
+    account.held = dec!(1);
+    assert_eq!(account.checked_total(), None);
+    engine.accounts.insert(3, account);
+    engine.accounts.insert(1, Account::new(1));
+
+    let err = engine.export_accounts(Vec::new()).unwrap_err();
+    assert!(matches!(err, ExportError::TotalOverflow(3)));
+    assert_eq!(err.to_string(), "total funds of client 3 overflow");
+    assert!(matches!(engine.print_report(Vec::new()), Err(ExportError::TotalOverflow(3))));
+}
+
+#[test]
+fn test_opening_balance() {
+    let mut engine = PaymentEngine::new();
+    engine.set_opening_balance(1, dec!(100), false).unwrap();
+    engine.set_opening_balance(2, dec!(5), true).unwrap();
+
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,50\n\
+                 withdrawal,1,2,30\n\
+                 deposit,2,3,10\n\
+                 deposit,3,4,10\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(120));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(5));
+    assert_eq!(engine.counts().deposit.rejected, 1);
+
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,120.0000,0.0000,120.0000,false\n\
+         2,5.0000,0.0000,5.0000,true\n\
+         3,10.0000,0.0000,10.0000,false\n"
+    );
+
+    // Clients with an account already cannot be given an opening balance
+    assert_eq!(
+        engine.set_opening_balance(3, dec!(1), false),
+        Err(OpeningBalanceError::ExistingAccount(3))
+    );
+    assert_eq!(engine.accounts.get(&3).unwrap().available, dec!(10));
+}
+
+#[test]
+fn test_peak_held() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,2,2,40\n\
+                 dispute,1,1,\n\
+                 dispute,2,2,\n\
+                 resolve,1,1,\n\
+                 resolve,2,2,10\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Both disputes were open at once before being resolved down to 30 held
+    assert_eq!(engine.peak_held(), dec!(140));
+    let held: Decimal = engine.accounts.values().map(|account| account.held).sum();
+    assert_eq!(held, dec!(30));
+
+    // A later, smaller dispute does not lower the peak
+    let input = "type,client,tx,amount\n\
+                 deposit,3,3,20\n\
+                 dispute,3,3,\n\
+                 chargeback,3,3,\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.peak_held(), dec!(140));
+}
+
+#[test]
+fn test_export_accounts_with_renamed_headers() {
+    let mut engine = PaymentEngine::new();
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let mut format = CsvFormat::default();
+    format.headers[0] = "account_id".to_string();
+    format.headers[1] = "available_balance".to_string();
+    let mut output = Vec::new();
+    engine.export_accounts_with(&mut output, &format).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "account_id,available_balance,held,total,locked\n\
+         1,100.0000,0.0000,100.0000,false\n"
+    );
+}
+
+#[test]
+fn test_resolve_and_chargeback_reject_reasons() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 deposit,1,3,20\n\
+                 dispute,1,2,\n\
+                 resolve,1,2,\n\
+                 dispute,1,3,\n\
+                 chargeback,1,3,\n";
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        allow_dispute_on_locked: true,
+        ..Default::default()
+    });
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let tx = |transaction_type, tx_id| InputTransaction {
+        transaction_type,
+        client_id: 1,
+        tx_id,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    for transaction_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+        assert_eq!(engine.apply(tx(transaction_type, 1)), Err(RejectReason::NotDisputed));
+        assert_eq!(engine.apply(tx(transaction_type, 2)), Err(RejectReason::AlreadyResolved));
+        assert_eq!(engine.apply(tx(transaction_type, 3)), Err(RejectReason::AlreadyChargedBack));
+    }
+}
+
+#[test]
+fn test_export_transactions() {
+    let input = "type,client,tx,amount\n\
+                 deposit,2,5,10\n\
+                 deposit,1,3,100\n\
+                 withdrawal,1,4,30.5\n\
+                 dispute,1,3,\n\
+                 resolve,1,3,\n\
+                 dispute,2,5,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let mut output = Vec::new();
+    engine.export_transactions(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "tx,client,amount,dispute_status\n\
+         3,1,100.0000,resolved\n\
+         4,1,30.5000,not_disputed\n\
+         5,2,10.0000,disputed\n"
+    );
+}
+
+#[test]
+fn test_export_transactions_empty() {
+    let engine = PaymentEngine::new();
+    let mut output = Vec::new();
+    engine.export_transactions(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "tx,client,amount,dispute_status\n");
+}
+
+/// Builds an engine with the held funds check whose stored deposit claims more than was
+/// deposited, as an accounting bug would.
+fn engine_with_inflated_deposit(check: HeldCheck) -> PaymentEngine {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        held_check: Some(check),
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n";
+    engine.process_transactions(input.as_bytes()).unwrap();
+    engine.transactions.get_mut(&1).unwrap().amount = dec!(1000);
+    engine
+}
+
+#[test]
+fn test_held_check_error() {
+    let mut engine = engine_with_inflated_deposit(HeldCheck::Error);
+    let err = engine.process_transactions("type,client,tx,amount\ndispute,1,1,\n".as_bytes());
+    assert_eq!(err.unwrap_err().to_string(), "transaction 1: held funds exceed net deposits");
+    assert_eq!(engine.counts().dispute.rejected, 1);
+
+    // The dispute is rolled back
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!((account.available, account.held), (dec!(10), dec!(0)));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+
+    // Withdrawals count against net deposits, so one overdrawing an account with funds held
+    // fails the check too
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        held_check: Some(HeldCheck::Error),
+        overdraft_limit: Some(dec!(50)),
+        audit: true,
+        ..Default::default()
+    });
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 withdrawal,1,2,50\n";
+    let err = engine.process_transactions(input.as_bytes()).unwrap_err();
+    assert_eq!(err.to_string(), "transaction 2: held funds exceed net deposits");
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!((account.available, account.held), (dec!(0), dec!(100)));
+    assert!(!engine.transactions.contains_key(&2));
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+    assert_eq!(engine.audit_entries().len(), 2);
+
+    // Without the check the same bug goes unnoticed
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,1,10\n".as_bytes()).unwrap();
+    engine.transactions.get_mut(&1).unwrap().amount = dec!(1000);
+    engine.process_transactions("type,client,tx,amount\ndispute,1,1,\n".as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(1000));
+}
+
+#[test]
+#[should_panic(expected = "transaction 1: held funds")]
+fn test_held_check_panic() {
+    let mut engine = engine_with_inflated_deposit(HeldCheck::Panic);
+    let dispute_tx = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    let _ = engine.apply(dispute_tx);
+}
+
+#[test]
+fn test_partial_chargeback() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 dispute,1,1,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let chargeback = |amount| InputTransaction {
+        transaction_type: TransactionType::Chargeback,
+        client_id: 1,
+        tx_id: 1,
+        amount: Some(amount),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(chargeback(dec!(0))), Err(RejectReason::NonPositiveAmount));
+    assert_eq!(engine.apply(chargeback(dec!(100.01))), Err(RejectReason::ExceedsHeldAmount));
+
+    // 30 of the 100 held is charged back and the other 70 returns to available
+    engine.apply(chargeback(dec!(30))).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(120));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(account.total(), dec!(120));
+    assert!(account.locked);
+    let stored = engine.transactions.get(&1).unwrap();
+    assert_eq!(stored.dispute_status, DisputeStatus::ChargedBack);
+    assert_eq!(stored.held, dec!(0));
+
+    // Charging back the full held amount behaves like a chargeback without an amount
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,100\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0));
+    assert_eq!(account.held, dec!(0));
+    assert!(account.locked);
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn test_force_dispute_status() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 dispute,1,1,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    engine.force_dispute_status(1, DisputeStatus::NotDisputed).unwrap();
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+    assert_eq!(
+        engine.admin_actions(),
+        &[AdminAction::ForceDisputeStatus {
+            tx_id: 1,
+            from: DisputeStatus::Disputed,
+            to: DisputeStatus::NotDisputed,
+        }]
+    );
+
+    // Balances are left for the caller to correct
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(0));
+    assert_eq!(account.held, dec!(100));
+
+    assert_eq!(
+        engine.force_dispute_status(2, DisputeStatus::Resolved),
+        Err(DisputeStatusError::UnknownTransaction(2))
+    );
+}
+
+#[test]
+fn test_expected_clients_is_only_a_hint() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=200 {
+        input.push_str(&format!("deposit,{},{},1.5\n", tx % 50, tx));
+    }
+    let mut reference = PaymentEngine::new();
+    reference.process_transactions(input.as_bytes()).unwrap();
+
+    // Estimates that are too small or too large give the same result
+    for expected_clients in [1, 50, 10_000] {
+        let config = EngineConfig { expected_clients: Some(expected_clients), ..Default::default() };
+        let mut engine = PaymentEngine::with_config(config);
+        engine.process_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.accounts.len(), 50);
+        assert!(engine.diff(&reference).is_empty());
+    }
+}
+
+#[test]
+fn test_orphan_dispute_applied_when_deposit_arrives() {
+    let input = "type,client,tx,amount\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n\
+                 dispute,1,2,\n\
+                 deposit,1,1,100\n\
+                 deposit,1,3,50\n\
+                 dispute,1,9,\n";
+    let config = EngineConfig { buffer_orphan_disputes: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // The dispute and resolve of tx 1 were applied, in order, once the deposit arrived
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(150));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::Resolved);
+    assert_eq!(engine.counts().dispute.applied, 1);
+    assert_eq!(engine.counts().resolve.applied, 1);
+
+    // Disputes of transactions never seen are still pending at the end
+    let pending: Vec<_> = engine.pending_disputes().map(|tx| tx.tx_id).collect();
+    assert_eq!(pending, [2, 9]);
+
+    // Without buffering the orphans are rejected
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+    assert_eq!(engine.counts().dispute.rejected, 3);
+    assert_eq!(engine.pending_disputes().count(), 0);
+}
+
+#[test]
+fn test_merge_applies_orphan_disputes() {
+    let config = EngineConfig { buffer_orphan_disputes: true, ..Default::default() };
+    let mut first = PaymentEngine::with_config(config.clone());
+    first.process_transactions("type,client,tx,amount\ndeposit,1,1,100\n".as_bytes()).unwrap();
+    let mut second = PaymentEngine::with_config(config);
+    second.process_transactions("type,client,tx,amount\ndispute,1,1,\n".as_bytes()).unwrap();
+    assert_eq!(second.pending_disputes().count(), 1);
+
+    first.merge(second).unwrap();
+    assert_eq!(first.pending_disputes().count(), 0);
+    assert_eq!(first.accounts.get(&1).unwrap().held, dec!(100));
+}
+
+#[test]
+fn test_orphan_dispute_of_evicted_transaction_rejected() {
+    let config = EngineConfig {
+        buffer_orphan_disputes: true,
+        retention: Some(1),
+        ..Default::default()
+    };
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 dispute,1,1,\n\
+                 dispute,1,3,\n";
+    let mut engine = PaymentEngine::with_config(config.clone());
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Transaction 1 was evicted, so its dispute can never match and is rejected as unknown
+    assert_eq!(engine.evictions(), 1);
+    let pending: Vec<_> = engine.pending_disputes().map(|tx| tx.tx_id).collect();
+    assert_eq!(pending, [3]);
+    assert_eq!(engine.counts().dispute.rejected, 1);
+
+    // Disputes buffered by another engine for a transaction this one evicted are rejected
+    let mut other = PaymentEngine::with_config(config);
+    other.process_transactions("type,client,tx,amount\nresolve,1,1,\n".as_bytes()).unwrap();
+    assert_eq!(other.pending_disputes().count(), 1);
+    engine.merge(other).unwrap();
+    let pending: Vec<_> = engine.pending_disputes().map(|tx| tx.tx_id).collect();
+    assert_eq!(pending, [3]);
+    assert_eq!(engine.counts().resolve.rejected, 1);
+}
+
+#[test]
+fn test_merge_propagates_fatal_orphan_rejections() {
+    let config = EngineConfig {
+        buffer_orphan_disputes: true,
+        held_check: Some(HeldCheck::Error),
+        ..Default::default()
+    };
+    let mut first = PaymentEngine::with_config(config.clone());
+    first
+        .process_transactions("type,client,tx,amount
+deposit,1,1,100
+withdrawal,1,2,60
+".as_bytes())
+        .unwrap();
+    let mut second = PaymentEngine::with_config(config);
+    second.process_transactions("type,client,tx,amount
+dispute,1,1,
+".as_bytes()).unwrap();
+
+    // Holding the whole deposit exceeds what remains of it after the withdrawal
+    assert_eq!(
+        first.merge(second),
+        Err(MergeError::Rejected { tx_id: 1, reason: RejectReason::HeldExceedsNetDeposits })
+    );
+    assert_eq!(first.accounts.get(&1).unwrap().held, dec!(0));
+    assert_eq!(first.counts().dispute.rejected, 1);
+}
+
+#[test]
+fn test_account_status() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,2,2,100\n\
+                 dispute,2,2,\n\
+                 deposit,3,3,100\n\
+                 deposit,3,4,50\n\
+                 dispute,3,3,\n\
+                 chargeback,3,3,\n\
+                 dispute,3,4,\n\
+                 deposit,4,5,100\n\
+                 dispute,4,5,\n\
+                 resolve,4,5,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.account_status(1), Some(AccountStatus::Healthy));
+    assert_eq!(engine.account_status(2), Some(AccountStatus::HasActiveDispute));
+    // A chargeback locks the account, which is then frozen
+    assert_eq!(engine.account_status(3), Some(AccountStatus::Frozen));
+    // A resolved dispute is no longer active
+    assert_eq!(engine.account_status(4), Some(AccountStatus::Healthy));
+    assert_eq!(engine.account_status(5), None);
+}
+
+#[test]
+fn test_max_transactions() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,10\n\
+                 dispute,1,1,\n\
+                 deposit,1,3,5\n\
+                 deposit,1,4,5\n";
+    let config = EngineConfig { max_transactions: Some(2), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    let err = engine.process_transactions(input.as_bytes()).err().unwrap();
+
+    // Disputes store nothing, so processing stops at the third deposit or withdrawal
+    assert!(matches!(err, AccountantError::Rejected { tx_id: 3, reason: RejectReason::TransactionLimitExceeded }));
+    assert_eq!(err.to_string(), "transaction 3: the limit on stored transactions was reached");
+    assert!(RejectReason::TransactionLimitExceeded.halts_processing());
+    assert!(!RejectReason::InsufficientFunds.halts_processing());
+    assert_eq!(engine.transactions.len(), 2);
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(100));
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-10));
+}
+
+#[test]
+fn test_export_accounts_sorted_by() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,2,2,50\n\
+                 deposit,3,3,10\n\
+                 deposit,4,4,30\n\
+                 deposit,4,5,5\n\
+                 dispute,4,5,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let export = |key| {
+        let mut output = Vec::new();
+        engine.export_accounts_sorted_by(&mut output, key).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Clients 1 and 3 tie on total and keep their client ID order
+    assert_eq!(export(SortKey::TotalDesc), ["2", "4", "1", "3"]);
+    assert_eq!(export(SortKey::HeldDesc), ["4", "1", "2", "3"]);
+    assert_eq!(export(SortKey::AvailableDesc), ["2", "4", "1", "3"]);
+    assert_eq!(export(SortKey::ClientId), ["1", "2", "3", "4"]);
+
+    let mut output = Vec::new();
+    engine.export_accounts_sorted_by(&mut output, SortKey::TotalDesc).unwrap();
+    assert!(String::from_utf8(output).unwrap().starts_with("client,available,held,total,locked\n2,50.0000,"));
+}
+
+#[test]
+fn test_process_transactions_limited() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,2,2,50\n\
+                 not-a-row\n\
+                 dispute,1,1,\n\
+                 withdrawal,2,3,20\n\
+                 chargeback,1,1,\n";
+    let mut limited = PaymentEngine::new();
+    limited.process_transactions_limited(input.as_bytes(), 4).unwrap();
+
+    // Same state as processing the header and the first four rows
+    let truncated: Vec<_> = input.lines().take(5).collect();
+    let mut reference = PaymentEngine::new();
+    reference.process_transactions(truncated.join("\n").as_bytes()).unwrap();
+    assert!(limited.diff(&reference).is_empty());
+    assert_eq!(limited.counts(), reference.counts());
+    assert_eq!(limited.counts().total(), 4);
+    assert_eq!(limited.accounts.get(&1).unwrap().held, dec!(100));
+
+    // A limit beyond the end processes everything
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions_limited(input.as_bytes(), 100).unwrap();
+    assert_eq!(engine.counts().total(), 6);
+}
+
+#[test]
+fn test_state_json_round_trip() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.25\n\
+                 deposit,2,2,50\n\
+                 withdrawal,1,3,20\n\
+                 dispute,1,1,\n\
+                 dispute,2,2,\n\
+                 chargeback,2,2,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    let accounts = serde_json::to_string(&engine.accounts).unwrap();
+    let transactions = serde_json::to_string(&engine.transactions).unwrap();
+    let restored_accounts: std::collections::HashMap<ClientId, Account> = serde_json::from_str(&accounts).unwrap();
+    let restored_transactions: std::collections::HashMap<TransactionId, StoredTransaction> =
+        serde_json::from_str(&transactions).unwrap();
+
+    assert_eq!(restored_accounts, engine.accounts);
+    assert_eq!(restored_transactions, engine.transactions);
+    assert_eq!(restored_transactions[&2].dispute_status, DisputeStatus::ChargedBack);
+    assert!(transactions.contains(r#""dispute_status":"charged_back""#));
+
+    // State saved before accounts counted disputes still loads, with no disputes counted
+    let mut saved = serde_json::to_value(&engine.accounts[&1]).unwrap();
+    saved.as_object_mut().unwrap().remove("disputes").unwrap();
+    let restored: Account = serde_json::from_value(saved).unwrap();
+    assert_eq!(restored.disputes, 0);
+    assert_eq!(restored.held, engine.accounts[&1].held);
+}
+
+#[test]
+fn test_max_amount() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1000\n\
+                 deposit,1,2,1000000000000000000\n\
+                 withdrawal,1,3,1000.01\n\
+                 withdrawal,1,4,400\n";
+    let config = EngineConfig { max_amount: Some(dec!(1000)), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Amounts at the cap are accepted, those above it ignored
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(600));
+    assert!(!engine.transactions.contains_key(&2));
+    assert_eq!(engine.counts().deposit, TypeCounts { applied: 1, rejected: 1 });
+    assert_eq!(engine.counts().withdrawal, TypeCounts { applied: 1, rejected: 1 });
+
+    let deposit_tx = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 5,
+        amount: Some(dec!(1000.0001)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.handle_deposit(deposit_tx), Err(RejectReason::AmountAboveMaximum));
+}
+
+#[test]
+fn test_net_position() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 withdrawal,1,2,25\n\
+                 deposit,2,3,60\n\
+                 deposit,2,4,40\n\
+                 dispute,2,3,\n\
+                 deposit,3,5,30\n\
+                 deposit,3,6,20\n\
+                 dispute,3,5,\n\
+                 chargeback,3,5,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    assert_eq!(
+        engine.net_position(),
+        NetPosition {
+            available: dec!(135),
+            held: dec!(60),
+            unlocked: dec!(175),
+            locked: dec!(20),
+            total: dec!(195),
+        }
+    );
+    assert_eq!(PaymentEngine::new().net_position().total, dec!(0));
+}
+
+#[test]
+fn test_reference_passthrough() {
+    let input = "type,client,tx,amount,reference\n\
+                 deposit,1,1,100,ref-a\n\
+                 deposit,1,2,50,ref-b\n\
+                 withdrawal,1,3,500,ref-rejected\n\
+                 dispute,1,1,,\n\
+                 deposit,2,4,10,\n";
+    let config = EngineConfig { checksum_row: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Rejected transactions and transactions without a reference leave it unchanged
+    assert_eq!(engine.accounts.get(&1).unwrap().last_reference.as_deref(), Some("ref-b"));
+    assert_eq!(engine.accounts.get(&2).unwrap().last_reference, None);
+
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked,reference\n\
+         1,50.0000,100.0000,150.0000,false,ref-b\n\
+         2,10.0000,0.0000,10.0000,false,\n\
+         checksum,,,160.0000,,\n"
+    );
+
+    // Without references the default schema is unchanged
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,1,100\n".as_bytes()).unwrap();
+    let mut output = Vec::new();
+    engine.export_accounts(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n");
+}
+
+#[test]
+fn test_chargeback_policy() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 withdrawal,1,3,20\n";
+
+    // By default the chargeback freezes the account
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert!(account.locked);
+    assert_eq!(account.available, dec!(50));
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+
+    let config = EngineConfig { chargeback_policy: ChargebackPolicy::KeepUnlocked, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    let account = engine.accounts.get(&1).unwrap();
+    assert!(!account.locked);
+    assert_eq!(account.available, dec!(30));
+    assert_eq!(account.held, dec!(0));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::ChargedBack);
+    assert_eq!(engine.counts().withdrawal.applied, 1);
+}
+
+#[test]
+fn test_valid_transition() {
+    use DisputeStatus::*;
+    assert!(valid_transition(NotDisputed, Disputed));
+    assert!(valid_transition(Resolved, Disputed));
+    assert!(valid_transition(Disputed, Resolved));
+    assert!(valid_transition(Disputed, ChargedBack));
+    assert!(valid_transition(NotDisputed, Reversed));
+    assert!(valid_transition(ChargedBack, ChargedBack));
+
+    assert!(!valid_transition(NotDisputed, ChargedBack));
+    assert!(!valid_transition(NotDisputed, Resolved));
+    assert!(!valid_transition(ChargedBack, Disputed));
+    assert!(!valid_transition(Reversed, Disputed));
+    assert!(!valid_transition(Disputed, Reversed));
+    assert!(!valid_transition(Rejected, Disputed));
+}
+
+#[test]
+fn test_check_transitions() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,50\n\
+                 deposit,1,3,10\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n\
+                 reversal,1,3,\n\
+                 dispute,1,3,\n\
+                 dispute,1,1,\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 resolve,1,1,\n";
+    let config = EngineConfig { check_transitions: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::ChargedBack);
+    assert_eq!(engine.transactions.get(&2).unwrap().dispute_status, DisputeStatus::NotDisputed);
+    assert_eq!(engine.transactions.get(&3).unwrap().dispute_status, DisputeStatus::Reversed);
+}
+
+#[test]
+fn test_reused_charged_back_id() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2,30\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n";
+    let reuse = |client_id| InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id,
+        tx_id: 1,
+        amount: Some(dec!(25)),
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.apply(reuse(2)), Err(RejectReason::ChargedBackIdReused));
+    let stored = engine.transactions.get(&1).unwrap();
+    assert_eq!(stored.client_id, 1);
+    assert_eq!(stored.dispute_status, DisputeStatus::ChargedBack);
+    assert!(engine.accounts.get(&1).unwrap().locked);
+    assert!(!engine.accounts.contains_key(&2));
+
+    // Even when the account stays usable the record is kept
+    let config = EngineConfig { chargeback_policy: ChargebackPolicy::KeepUnlocked, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.apply(reuse(1)), Err(RejectReason::ChargedBackIdReused));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::ChargedBack);
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(30));
+
+    let config = EngineConfig {
+        chargeback_policy: ChargebackPolicy::KeepUnlocked,
+        reused_id_policy: ReusedIdPolicy::Overwrite,
+        ..Default::default()
+    };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.apply(reuse(1)), Ok(()));
+    assert_eq!(engine.transactions.get(&1).unwrap().dispute_status, DisputeStatus::NotDisputed);
+}
+
+#[test]
+fn test_enforce_seq_order() {
+    let input = "type,client,tx,amount,seq\n\
+                 deposit,1,1,100,1\n\
+                 deposit,2,2,50,7\n\
+                 withdrawal,1,3,10,3\n\
+                 withdrawal,1,4,20,2\n\
+                 deposit,1,5,5,3\n\
+                 deposit,1,6,1,\n\
+                 dispute,1,1,,2\n";
+    let config = EngineConfig { enforce_seq_order: true, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // Sequence numbers are per client, equal ones pass and rows without one are not checked
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(96));
+    assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0));
+    assert_eq!(engine.counts().withdrawal.rejected, 1);
+    assert_eq!(engine.counts().dispute.rejected, 1);
+    assert!(!engine.transactions.contains_key(&4));
+
+    let replayed = InputTransaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: 1,
+        tx_id: 7,
+        amount: Some(dec!(100)),
+        currency: None,
+        reference: None,
+        seq: Some(1),
+    };
+    assert_eq!(engine.apply(replayed.clone()), Err(RejectReason::OutOfSequence));
+
+    // Without the check the order is ignored
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(-24));
+    assert_eq!(engine.apply(replayed), Ok(()));
+}
+
+#[test]
+fn test_sweep_dust() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,2,2,5\n\
+                 deposit,3,3,5\n\
+                 deposit,4,4,0.00005\n\
+                 deposit,5,5,0.0001\n\
+                 deposit,6,6,1\n\
+                 dispute,4,4,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    // Processing never leaves held funds outside a dispute, so the dust is set directly
+    engine.accounts.get_mut(&1).unwrap().held = dec!(0.0001);
+    engine.accounts.get_mut(&2).unwrap().held = dec!(0.0002);
+    engine.accounts.get_mut(&2).unwrap().locked = true;
+    engine.accounts.get_mut(&3).unwrap().held = dec!(0.5);
+    engine.accounts.get_mut(&5).unwrap().held = dec!(0.9);
+    engine.accounts.get_mut(&5).unwrap().available = Decimal::MAX;
+    engine.accounts.get_mut(&6).unwrap().held = dec!(0.0004);
+
+    assert_eq!(engine.sweep_dust(dec!(0.001)), vec![(1, dec!(0.0001)), (6, dec!(0.0004))]);
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(10.0001));
+    assert_eq!(account.held, dec!(0));
+    // Held funds of locked accounts, above the threshold or under an active dispute stay put
+    assert_eq!(engine.accounts.get(&2).unwrap().held, dec!(0.0002));
+    assert_eq!(engine.accounts.get(&3).unwrap().held, dec!(0.5));
+    assert_eq!(engine.accounts.get(&4).unwrap().held, dec!(0.00005));
+
+    // So do held funds that would overflow available funds
+    assert_eq!(engine.sweep_dust(dec!(1)), vec![(3, dec!(0.5))]);
+    assert_eq!(engine.accounts.get(&5).unwrap().held, dec!(0.9));
+
+    assert_eq!(engine.sweep_dust(dec!(0.001)), vec![]);
+}
+
+#[test]
+fn test_thousands_separators() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,\"1,234.50\"\n\
+                 deposit,2,2,1234.50\n";
+
+    // By default the separator makes the amount malformed
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.counts().malformed, 1);
+    assert!(!engine.accounts.contains_key(&1));
+
+    let config = EngineConfig { separators: SeparatorPolicy::CommaThousands, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config.clone());
+    engine.process_transactions(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(1234.50));
+    assert_eq!(engine.accounts.get(&2).unwrap().available, dec!(1234.50));
+
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions_strict(input.as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(1234.50));
+
+    let config = EngineConfig { separators: SeparatorPolicy::DotThousands, ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions("type,client,tx,amount\ndeposit,1,1,\"1.234,50\"\n".as_bytes()).unwrap();
+    assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(1234.50));
+}
+
+#[test]
+fn test_max_disputes_per_client() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,1,2,10\n\
+                 deposit,1,3,10\n\
+                 deposit,2,4,10\n\
+                 dispute,1,1,\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n\
+                 dispute,1,2,\n\
+                 dispute,1,3,\n\
+                 dispute,2,4,\n";
+    let config = EngineConfig { max_disputes_per_client: Some(2), ..Default::default() };
+    let mut engine = PaymentEngine::with_config(config);
+    engine.process_transactions(input.as_bytes()).unwrap();
+
+    // The duplicate dispute of tx 1 is rejected for another reason and does not count
+    assert_eq!(engine.accounts.get(&1).unwrap().disputes, 2);
+    assert_eq!(engine.transactions.get(&2).unwrap().dispute_status, DisputeStatus::Disputed);
+    assert_eq!(engine.transactions.get(&3).unwrap().dispute_status, DisputeStatus::NotDisputed);
+    assert_eq!(engine.transactions.get(&4).unwrap().dispute_status, DisputeStatus::Disputed);
+    assert_eq!(engine.counts().dispute.applied, 3);
+    assert_eq!(engine.counts().dispute.rejected, 2);
+
+    let dispute = InputTransaction {
+        transaction_type: TransactionType::Dispute,
+        client_id: 1,
+        tx_id: 1,
+        amount: None,
+        currency: None,
+        reference: None,
+        seq: None,
+    };
+    assert_eq!(engine.apply(dispute), Err(RejectReason::DisputeLimitExceeded));
+}
@@ -0,0 +1,91 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rs_accountant::engine::{EngineConfig, PaymentEngine};
+use rs_accountant::generator;
+use rs_accountant::store::Store;
+use rust_decimal::Decimal;
+use std::collections::btree_map::{self, BTreeMap};
+
+/// A store over a `BTreeMap`, standing in for a backend other than the default `HashMap`.
+struct OrderedStore<K, V>(BTreeMap<K, V>);
+
+impl<K, V> IntoIterator for OrderedStore<K, V> {
+    type Item = (K, V);
+    type IntoIter = btree_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<K: Ord, V> Store<K, V> for OrderedStore<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        self.0.get(key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.0.get_mut(key)
+    }
+
+    fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        self.0.entry(key).or_insert_with(default)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.0.insert(key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.0.remove(key)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a K, &'a V)>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        self.0.iter()
+    }
+}
+
+#[test]
+fn test_custom_store_matches_in_memory() {
+    for seed in 0..5 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (transactions, _) = generator::generate(&mut rng, 50, 1000);
+        let mut input = Vec::new();
+        generator::write_transactions(&transactions, &mut input).unwrap();
+
+        let mut expected_engine = PaymentEngine::new();
+        expected_engine.process_transactions(input.as_slice()).unwrap();
+        let mut expected = Vec::new();
+        expected_engine.export_accounts(&mut expected).unwrap();
+
+        let mut engine: PaymentEngine<Decimal, OrderedStore<_, _>, OrderedStore<_, _>> = PaymentEngine::with_stores(
+            EngineConfig::default(),
+            OrderedStore(BTreeMap::new()),
+            OrderedStore(BTreeMap::new()),
+        );
+        engine.process_transactions(input.as_slice()).unwrap();
+        let mut actual = Vec::new();
+        engine.export_accounts(&mut actual).unwrap();
+
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            String::from_utf8(expected).unwrap(),
+            "seed {}",
+            seed
+        );
+        assert_eq!(engine.counts(), expected_engine.counts(), "seed {}", seed);
+        assert_eq!(
+            engine.transactions.len(),
+            expected_engine.transactions.len(),
+            "seed {}",
+            seed
+        );
+    }
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "async")]
+
+use futures::executor::block_on;
+use futures::stream;
+use rs_accountant::engine::*;
+use rust_decimal_macros::dec;
+
+fn transaction(transaction_type: TransactionType, tx_id: u32, amount: Option<rust_decimal::Decimal>) -> InputTransaction {
+    InputTransaction {
+        transaction_type,
+        client_id: 1,
+        tx_id,
+        amount,
+        currency: None,
+        reference: None,
+        seq: None,
+    }
+}
+
+#[test]
+fn test_process_stream() {
+    let transactions = vec![
+        transaction(TransactionType::Deposit, 1, Some(dec!(100))),
+        transaction(TransactionType::Withdrawal, 2, Some(dec!(30))),
+        transaction(TransactionType::Withdrawal, 3, Some(dec!(500))),
+        transaction(TransactionType::Dispute, 1, None),
+    ];
+    let mut engine = PaymentEngine::new();
+    block_on(engine.process_stream(stream::iter(transactions))).unwrap();
+
+    let account = engine.accounts.get(&1).unwrap();
+    assert_eq!(account.available, dec!(-30));
+    assert_eq!(account.held, dec!(100));
+    assert_eq!(engine.counts().withdrawal, TypeCounts { applied: 1, rejected: 1 });
+}
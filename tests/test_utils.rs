@@ -0,0 +1,43 @@
+#![cfg(feature = "test-utils")]
+
+use rs_accountant::engine::PaymentEngine;
+use rs_accountant::test_utils::assert_accounts_match;
+
+fn engine() -> PaymentEngine {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,2,2,5.5\n\
+                 dispute,2,2,\n";
+    let mut engine = PaymentEngine::new();
+    engine.process_transactions(input.as_bytes()).unwrap();
+    engine
+}
+
+#[test]
+fn test_assert_accounts_match() {
+    // Order, formatting and surrounding whitespace do not matter
+    let expected = "client, available, held, total, locked\n\
+                    2, 0, 5.5, 5.50, false\n\
+                    1, 10.0000, 0.0000, 10.0000, false\n";
+    assert_accounts_match(&engine(), expected);
+}
+
+#[test]
+#[should_panic(expected = "client 1: expected available 9.0000, held 0.0000, total 9.0000, unlocked, \
+                           got available 10.0000, held 0.0000, total 10.0000, unlocked\n\
+                           client 2: unexpected account")]
+fn test_assert_accounts_match_reports_mismatches() {
+    let expected = "client,available,held,total,locked\n\
+                    1,9,0,9,false\n";
+    assert_accounts_match(&engine(), expected);
+}
+
+#[test]
+#[should_panic(expected = "client 3: expected available 1.0000, held 0.0000, total 1.0000, unlocked, got no account")]
+fn test_assert_accounts_match_reports_missing_accounts() {
+    let expected = "client,available,held,total,locked\n\
+                    1,10,0,10,false\n\
+                    2,0,5.5,5.5,false\n\
+                    3,1,0,1,false\n";
+    assert_accounts_match(&engine(), expected);
+}
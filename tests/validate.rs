@@ -0,0 +1,73 @@
+use rs_accountant::validate::{validate_csv_structure, CsvErrorKind, CsvStats};
+
+#[test]
+fn test_valid_input() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.5\n\
+                 withdrawal,1,2,20\n\
+                 dispute,1,1,\n\
+                 adjustment,1,3,-5\n";
+    let stats = validate_csv_structure(input.as_bytes()).unwrap();
+    assert_eq!(stats, CsvStats { rows: 4 });
+}
+
+#[test]
+fn test_semantic_errors_are_not_reported() {
+    // Overdrawing and disputing an unknown transaction are engine rules, not structure
+    let input = "type,client,tx,amount\n\
+                 withdrawal,1,1,500\n\
+                 chargeback,1,99,\n";
+    assert!(validate_csv_structure(input.as_bytes()).is_ok());
+}
+
+#[test]
+fn test_missing_header_column() {
+    let input = "type,client,tx\n\
+                 dispute,1,1\n";
+    let errors = validate_csv_structure(input.as_bytes()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 1);
+    assert!(matches!(errors[0].kind, CsvErrorKind::MissingColumn("amount")));
+}
+
+#[test]
+fn test_structural_errors_with_lines() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100\n\
+                 deposit,1,2\n\
+                 refund,1,3,10\n\
+                 deposit,x,4,10\n\
+                 withdrawal,1,5,\n\
+                 deposit,1,6,1.2.3\n";
+    let errors = validate_csv_structure(input.as_bytes()).unwrap_err();
+    let found: Vec<_> = errors
+        .iter()
+        .map(|err| match &err.kind {
+            CsvErrorKind::FieldCount { found, .. } => (err.line, format!("fields {}", found)),
+            CsvErrorKind::InvalidField { column, .. } => (err.line, column.to_string()),
+            CsvErrorKind::MissingAmount => (err.line, "amount required".to_string()),
+            other => panic!("unexpected error: {:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (3, "fields 3".to_string()),
+            (4, "type".to_string()),
+            (5, "client".to_string()),
+            (6, "amount required".to_string()),
+            (7, "amount".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_comments_are_skipped() {
+    let input = "# generated by hand\n\
+                 type,client,tx,amount\n\
+                 \n\
+                 # first client\n\
+                 deposit,1,1,100\n";
+    let stats = validate_csv_structure(input.as_bytes()).unwrap();
+    assert_eq!(stats, CsvStats { rows: 1 });
+}